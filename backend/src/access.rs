@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{database::Database, project::{Ownership, Project}, team::Permissions};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    // handing a project over, managing who has access
+    Admin,
+    // archiving, unarchiving or deleting a project, the only changes an archived project still takes
+    Lifecycle
+}
+impl Access {
+    pub fn required(&self) -> Permissions {
+        match self {
+            Access::Read => Permissions::Viewer,
+            Access::Write => Permissions::Editor,
+            Access::Admin | Access::Lifecycle => Permissions::Admin
+        }
+    }
+
+    // archived projects are frozen, short of bringing them back or getting rid of them
+    pub fn allowed_on_archived(&self) -> bool {
+        matches!(self, Access::Read | Access::Lifecycle)
+    }
+}
+
+// what a route is about to touch, so the checks can work out which project it belongs to
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Target {
+    // not tied to a single project, e.g. listings or creating a new project
+    Global,
+    Project(u128),
+    Group(u128),
+    Task(u128),
+    // an account, so activity on keys, passwords and 2fa has somewhere to point
+    User(u128)
+}
+impl Target {
+    pub fn project(&self, db: &Database) -> Option<u128> {
+        match self {
+            Target::Global | Target::User(_) => None,
+            Target::Project(project_id) => Some(*project_id),
+            Target::Group(group_id) => db.index.group_project.get(group_id).copied(),
+            Target::Task(task_id) => db.index.task_group.get(task_id).and_then(|g| db.index.group_project.get(g)).copied()
+        }
+    }
+}
+
+pub fn team_permissions(db: &Database, user_id: u128, team_id: u128) -> Permissions {
+    db.teams.get(&team_id).and_then(|t| t.members.get(&user_id)).copied().unwrap_or(Permissions::None)
+}
+
+// what a user may do with a project: owners of user owned projects are always its admin,
+// then the project's own member entries, then the owning team's roles
+pub fn project_permissions(db: &Database, user_id: u128, project_id: u128) -> Permissions {
+    match db.projects.get(&project_id) {
+        Some(p) => permissions_in(db, user_id, p),
+        None => Permissions::None
+    }
+}
+
+// the same rules for a project that isn't in the database, like one sitting in the trash
+pub fn permissions_in(db: &Database, user_id: u128, project: &Project) -> Permissions {
+    if project.owner == Ownership::User(user_id) {
+        return Permissions::Admin;
+    }
+    if let Some(permissions) = project.members.get(&user_id) {
+        return *permissions;
+    }
+    match project.owner {
+        Ownership::Team(team_id) => team_permissions(db, user_id, team_id),
+        Ownership::User(_) => Permissions::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{database::Database, project::{Ownership, Project}, team::{Permissions, Team}};
+
+    use super::{project_permissions, Access};
+
+    #[test]
+    fn member_entries_override_team_roles() {
+        let mut db = Database::default();
+        db.teams.insert(1, Team { name: "team".to_string(), members: HashMap::from([(10, Permissions::Admin), (11, Permissions::Viewer)]) });
+        db.projects.insert(2, Project {
+            name: "team project".to_string(),
+            owner: Ownership::Team(1),
+            groups: vec![],
+            // a team viewer promoted, a contractor from outside, and the team admin shut out
+            members: HashMap::from([(11, Permissions::Editor), (12, Permissions::Editor), (10, Permissions::None)]),
+            archived: false,
+            offered_to: None,
+            created_at: 0,
+            created_by: None,
+            updated_at: 0,
+            updated_by: None
+        });
+        db.projects.insert(3, Project {
+            name: "own project".to_string(),
+            owner: Ownership::User(12),
+            groups: vec![],
+            members: HashMap::from([(12, Permissions::Viewer), (11, Permissions::Viewer)]),
+            archived: false,
+            offered_to: None,
+            created_at: 0,
+            created_by: None,
+            updated_at: 0,
+            updated_by: None
+        });
+
+        assert_eq!(project_permissions(&db, 11, 2), Permissions::Editor);
+        assert_eq!(project_permissions(&db, 12, 2), Permissions::Editor);
+        assert_eq!(project_permissions(&db, 10, 2), Permissions::None);
+        assert_eq!(project_permissions(&db, 13, 2), Permissions::None);
+
+        // the owner can't be demoted
+        assert_eq!(project_permissions(&db, 12, 3), Permissions::Admin);
+        assert_eq!(project_permissions(&db, 11, 3), Permissions::Viewer);
+        assert_eq!(project_permissions(&db, 10, 3), Permissions::None);
+    }
+
+    #[test]
+    fn archived_projects_only_take_reads_and_lifecycle_changes() {
+        assert!(Access::Read.allowed_on_archived());
+        assert!(Access::Lifecycle.allowed_on_archived());
+        assert!(!Access::Write.allowed_on_archived());
+        // transfers, members, share links and webhooks all count as admin changes
+        assert!(!Access::Admin.allowed_on_archived());
+        assert_eq!(Access::Lifecycle.required(), Access::Admin.required());
+    }
+}
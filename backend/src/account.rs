@@ -0,0 +1,229 @@
+use std::{collections::HashMap, path::PathBuf, sync::{Arc, RwLock}};
+
+use rocket::{data::{self, Data, FromData}, request::Request, State};
+use serde::{Deserialize, Serialize};
+
+use crate::{access::Target, activity::Activity, config, database::Database, login_info::{LoginInfoParseError, LoginInformation, LoginResult}, identity::{self, PasswordUpdate, Registration}, utils};
+
+pub const USERNAME_MIN_LENGTH: usize = 3;
+pub const USERNAME_MAX_LENGTH: usize = 32;
+pub const PASSWORD_MIN_LENGTH: usize = 8;
+pub const INVITE_LENGTH: usize = 24;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationMode {
+    Closed,
+    Invite,
+    Open
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum AccountError {
+    RegistrationClosed,
+    InviteInvalid,
+
+    UsernameInvalid,
+    PasswordTooShort,
+
+    UsernameTaken,
+    UserNoExist,
+
+    StoreUnavailable,
+    // the configured identity provider can't create accounts or change passwords
+    Unsupported
+}
+
+// single use, handed out by admins when registration is invite only
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Invite {
+    pub created_by: u128,
+    pub created_at: u128
+}
+impl Invite {
+    pub fn serialize(db: &Database) -> (PathBuf, String) {
+        (config::get().data_path("invites.json"), serde_json::to_string_pretty(&db.invites).unwrap())
+    }
+
+    pub fn load() -> HashMap<String, Invite> {
+        utils::load_or_default(config::get().data_path("invites.json"))
+    }
+}
+
+pub fn validate_username(username: &str) -> Result<(), AccountError> {
+    let length = username.chars().count();
+    if !(USERNAME_MIN_LENGTH..=USERNAME_MAX_LENGTH).contains(&length) {
+        return Err(AccountError::UsernameInvalid);
+    }
+    if !username.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.') {
+        return Err(AccountError::UsernameInvalid);
+    }
+    Ok(())
+}
+
+pub fn validate_password(password: &str) -> Result<(), AccountError> {
+    if password.chars().count() < PASSWORD_MIN_LENGTH {
+        return Err(AccountError::PasswordTooShort);
+    }
+    Ok(())
+}
+
+pub fn create_account(db: &RwLock<Database>, username: &String, password: &str) -> Result<u128, AccountError> {
+    validate_username(username)?;
+    validate_password(password)?;
+
+    // athena may already know the name from someone who logged in through a provider that keeps no ids
+    if db.read().unwrap().fetch_user_id(username).is_some() {
+        return Err(AccountError::UsernameTaken);
+    }
+
+    let user_id = match identity::get().register(username, password) {
+        Registration::Success(id) => id,
+        Registration::UsernameTaken => return Err(AccountError::UsernameTaken),
+        Registration::Unsupported => return Err(AccountError::Unsupported),
+        Registration::Unavailable => return Err(AccountError::StoreUnavailable)
+    };
+
+    let mut db = db.write().unwrap();
+    db.insert_user(user_id, username.clone());
+    Activity::record(&mut db, user_id, None, Target::User(user_id), "registered", None::<()>, Some(username));
+    db.save();
+    Ok(user_id)
+}
+
+pub fn set_password(username: &str, password: &str) -> Result<(), AccountError> {
+    match identity::get().set_password(username, password) {
+        PasswordUpdate::Success => Ok(()),
+        PasswordUpdate::UnknownUser => Err(AccountError::UserNoExist),
+        PasswordUpdate::Unsupported => Err(AccountError::Unsupported),
+        PasswordUpdate::Unavailable => Err(AccountError::StoreUnavailable)
+    }
+}
+
+// login details plus the password to switch to
+pub struct PasswordChange {
+    pub login: LoginInformation,
+    pub new_password: String
+}
+
+#[derive(Deserialize)]
+struct RawPasswordChange {
+    new_password: Option<String>
+}
+
+#[rocket::async_trait]
+impl<'l> FromData<'l> for PasswordChange {
+    type Error = LoginInfoParseError;
+
+    async fn from_data(req: &'l Request<'_>, data: Data<'l>) -> data::Outcome<'l, Self> {
+        let result = LoginInformation::read_body(req, data).await.and_then(|body| {
+            let login = LoginInformation::parse(body.as_str())?;
+            let raw: RawPasswordChange = serde_json::from_str(body.as_str()).map_err(|_| LoginInfoParseError::ParsingError)?;
+            Ok(PasswordChange {
+                login: LoginInformation { ip: req.client_ip(), ..login },
+                new_password: raw.new_password.ok_or(LoginInfoParseError::MissingNewPassword)?
+            })
+        });
+        LoginInformation::outcome(req, result)
+    }
+}
+
+fn registration_response(result: Result<u128, AccountError>) -> String {
+    match result {
+        Ok(user_id) => utils::parse_response(Ok(user_id)),
+        Err(AccountError::UsernameTaken) => utils::parse_response(Err(LoginResult::UsernameTaken)),
+        Err(e) => utils::parse_response(Err(e))
+    }
+}
+
+// #region api calls
+#[post("/", data="<login>")]
+pub fn register(db: &State<Arc<RwLock<Database>>>, login: LoginInformation) -> String {
+    if config::get().registration != RegistrationMode::Open {
+        return utils::parse_response(Err(AccountError::RegistrationClosed));
+    }
+    registration_response(create_account(db, &login.username, &login.password))
+}
+
+#[post("/<code>", data="<login>")]
+pub fn register_with_invite(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, code: String) -> String {
+    if config::get().registration == RegistrationMode::Closed {
+        return utils::parse_response(Err(AccountError::RegistrationClosed));
+    }
+
+    // claimed up front so two registrations can't share one invite, handed back if registering fails
+    let invite = match db.write().unwrap().invites.remove(&code) {
+        Some(i) => i,
+        None => return utils::parse_response(Err(AccountError::InviteInvalid))
+    };
+
+    let result = create_account(db, &login.username, &login.password);
+    if result.is_err() {
+        db.write().unwrap().invites.insert(code, invite);
+    }
+    registration_response(result)
+}
+
+#[post("/", data="<login>")]
+pub fn invite(db: &State<Arc<RwLock<Database>>>, login: LoginInformation) -> String {
+    let result = login.login_admin(db);
+    match result {
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            let code = utils::generate_token(INVITE_LENGTH);
+            db.invites.insert(code.clone(), Invite {
+                created_by: user_id,
+                created_at: utils::get_time()
+            });
+            db.save();
+            utils::parse_response(Ok(code))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/", data="<change>")]
+pub fn change_password(db: &State<Arc<RwLock<Database>>>, change: PasswordChange) -> String {
+    let result = change.login.login(db);
+    match result {
+        LoginResult::Success(user_id) => {
+            if let Err(e) = validate_password(&change.new_password) {
+                return utils::parse_response(Err(e));
+            }
+            if let Err(e) = set_password(&change.login.username, &change.new_password) {
+                return utils::parse_response(Err(e));
+            }
+            let mut db = db.write().unwrap();
+            Activity::record(&mut db, user_id, None, Target::User(user_id), "password_changed", None::<()>, None::<()>);
+            db.save();
+            utils::parse_response(Ok("success"))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+// admin sets a new password for someone else, new_password in the body is theirs
+#[post("/<user_id>", data="<change>")]
+pub fn reset_password(db: &State<Arc<RwLock<Database>>>, change: PasswordChange, user_id: u128) -> String {
+    let result = change.login.login_admin(db);
+    match result {
+        LoginResult::Success(actor) => {
+            if let Err(e) = validate_password(&change.new_password) {
+                return utils::parse_response(Err(e));
+            }
+            let username = match db.read().unwrap().users.get(&user_id) {
+                Some(u) => u.username.clone(),
+                None => return utils::parse_response(Err(AccountError::UserNoExist))
+            };
+            if let Err(e) = set_password(&username, &change.new_password) {
+                return utils::parse_response(Err(e));
+            }
+            let mut db = db.write().unwrap();
+            Activity::record(&mut db, actor, None, Target::User(user_id), "password_reset", None::<()>, None::<()>);
+            db.save();
+            utils::parse_response(Ok("success"))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+// #endregion
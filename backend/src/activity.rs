@@ -0,0 +1,256 @@
+use std::{fs::{self, OpenOptions}, io::Write, sync::{Arc, Mutex, MutexGuard, RwLock}};
+
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{access::{Access, Target}, config, database::Database, login_info::{LoginInformation, LoginResult}, user::Role, utils};
+
+pub const DEFAULT_PAGE: usize = 50;
+pub const MAX_PAGE: usize = 200;
+
+// one entry per line, so a save only appends what was recorded since the last one
+const LOG_FILE: &str = "activity.jsonl";
+// the whole log in one pretty printed array, read once and replaced by LOG_FILE on the first save
+const LEGACY_FILE: &str = "activity.json";
+
+// id and time of the last entry on disk, None when the file has to be written from scratch
+static WRITTEN: Mutex<Option<(u64, u128)>> = Mutex::new(None);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ActivityError {
+    TaskNoExist,
+    NotAllowed
+}
+
+// one mutation, entries are only ever appended
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Activity {
+    // increasing, doubles as the pagination cursor
+    pub id: u64,
+    // None for maintenance commands run from the cli
+    pub actor: Option<u128>,
+    pub time: u128,
+
+    // kept on the entry since deleted things can't be traced back through the index anymore
+    pub project_id: Option<u128>,
+    pub entity: Target,
+    pub action: String,
+
+    pub before: Option<Value>,
+    pub after: Option<Value>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Page {
+    pub entries: Vec<Activity>,
+    // pass as `before` to get the next (older) page, None at the end
+    pub next: Option<u64>
+}
+
+// what a save has to put on disk, holds WRITTEN until it is written so two writers can't append the same entries
+pub struct Pending {
+    written: MutexGuard<'static, Option<(u64, u128)>>,
+    append: bool,
+    contents: String,
+    last: Option<(u64, u128)>
+}
+impl Pending {
+    pub fn write(mut self) {
+        let path = config::get().data_path(LOG_FILE);
+        let result = match self.append {
+            true if self.contents.is_empty() => return,
+            true => OpenOptions::new().append(true).create(true).open(&path).and_then(|mut f| f.write_all(self.contents.as_bytes())),
+            false => utils::write_atomic(&path, self.contents)
+        };
+        *self.written = match result {
+            Ok(_) => self.last,
+            Err(e) => {
+                println!("failed to write {}: {e}", path.display());
+                // a failed append may have left half a line behind
+                None
+            }
+        };
+    }
+}
+
+impl Activity {
+    // under the database's read lock, Pending::write does the I/O after it is let go
+    pub fn serialize(db: &Database) -> Pending {
+        let written = WRITTEN.lock().unwrap();
+        let (append, contents) = Activity::pending(&db.activity, *written);
+        let last = db.activity.last().map(|a| (a.id, a.time));
+        Pending { written, append, contents, last }
+    }
+
+    // the lines to append after `written`, or the whole log when it isn't part of it anymore (restores, reloads)
+    fn pending(activity: &[Activity], written: Option<(u64, u128)>) -> (bool, String) {
+        let start = written.and_then(|(id, time)| {
+            activity.binary_search_by_key(&id, |a| a.id).ok().filter(|i| activity[*i].time == time).map(|i| i + 1)
+        });
+        let contents = activity[start.unwrap_or(0)..].iter().map(|a| serde_json::to_string(a).unwrap() + "\n").collect();
+        (start.is_some(), contents)
+    }
+
+    pub fn load() -> Vec<Activity> {
+        let (result, intact) = match fs::read_to_string(config::get().data_path(LOG_FILE)) {
+            Ok(s) => Activity::parse(&s),
+            Err(_) => (utils::load_or_default(config::get().data_path(LEGACY_FILE)), false)
+        };
+        *WRITTEN.lock().unwrap() = if intact { result.last().map(|a| (a.id, a.time)) } else { None };
+        result
+    }
+
+    // false when a line didn't parse, e.g. cut off by a crash, so the next save rewrites the file
+    fn parse(contents: &str) -> (Vec<Activity>, bool) {
+        let lines = contents.lines().filter(|l| !l.trim().is_empty()).collect::<Vec<&str>>();
+        let result = lines.iter().filter_map(|l| serde_json::from_str(l).ok()).collect::<Vec<Activity>>();
+        let intact = result.len() == lines.len();
+        (result, intact)
+    }
+
+    // called by the mutating functions, the caller saves
+    pub fn record<B: Serialize, A: Serialize>(db: &mut Database, actor: impl Into<Option<u128>>, project_id: Option<u128>, entity: Target, action: &str, before: Option<B>, after: Option<A>) {
+        let id = db.activity.last().map_or(1, |a| a.id + 1);
+        db.activity.push(Activity {
+            id,
+            actor: actor.into(),
+            time: utils::get_time(),
+            project_id,
+            entity,
+            action: action.to_string(),
+            before: before.and_then(|b| serde_json::to_value(b).ok()),
+            after: after.and_then(|a| serde_json::to_value(a).ok())
+        });
+    }
+
+    // newest first
+    pub fn page(db: &Database, filter: impl Fn(&Activity) -> bool, before: Option<u64>, limit: Option<usize>) -> Page {
+        let limit = limit.unwrap_or(DEFAULT_PAGE).clamp(1, MAX_PAGE);
+        let mut entries = db.activity.iter().rev()
+            .filter(|a| before.is_none_or(|b| a.id < b))
+            .filter(|a| filter(a))
+            .take(limit + 1)
+            .cloned()
+            .collect::<Vec<Activity>>();
+        let next = if entries.len() > limit {
+            entries.truncate(limit);
+            entries.last().map(|a| a.id)
+        } else {
+            None
+        };
+        Page { entries, next }
+    }
+
+    // the project a task belonged to, also after it was deleted
+    pub fn project_of_task(db: &Database, task_id: u128) -> Option<u128> {
+        Target::Task(task_id).project(db).or_else(|| {
+            db.activity.iter().rev().find(|a| a.entity == Target::Task(task_id)).and_then(|a| a.project_id)
+        })
+    }
+}
+
+// #region api calls
+#[post("/<project_id>?<before>&<limit>", data="<login>")]
+pub fn project(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, project_id: u128, before: Option<u64>, limit: Option<usize>) -> String {
+    let result = login.authorize(db, Access::Read, Target::Project(project_id));
+    match result {
+        LoginResult::Success(_) => {
+            let db = db.read().unwrap();
+            utils::parse_response(Ok(Activity::page(&db, |a| a.project_id == Some(project_id), before, limit)))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/<task_id>?<before>&<limit>", data="<login>")]
+pub fn task(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, task_id: u128, before: Option<u64>, limit: Option<usize>) -> String {
+    let project_id = match Activity::project_of_task(&db.read().unwrap(), task_id) {
+        Some(p) => p,
+        None => return utils::parse_response(Err(ActivityError::TaskNoExist))
+    };
+    let result = login.authorize(db, Access::Read, Target::Project(project_id));
+    match result {
+        LoginResult::Success(_) => {
+            let db = db.read().unwrap();
+            utils::parse_response(Ok(Activity::page(&db, |a| a.entity == Target::Task(task_id), before, limit)))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+// everyone can see their own, server admins anyone's
+#[post("/<user_id>?<before>&<limit>", data="<login>")]
+pub fn user(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, user_id: u128, before: Option<u64>, limit: Option<usize>) -> String {
+    let result = login.authorize(db, Access::Read, Target::Global);
+    match result {
+        LoginResult::Success(caller) => {
+            let db = db.read().unwrap();
+            let admin = login.api_key.is_none() && (db.users.get(&caller).is_some_and(|u| u.role == Role::Admin) || config::get().is_admin(&login.username));
+            if caller != user_id && !admin {
+                return utils::parse_response(Err(ActivityError::NotAllowed));
+            }
+            utils::parse_response(Ok(Activity::page(&db, |a| a.actor == Some(user_id), before, limit)))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+// #endregion
+
+#[cfg(test)]
+mod tests {
+    use crate::{access::Target, database::Database};
+
+    use super::Activity;
+
+    #[test]
+    fn pages_newest_first() {
+        let mut db = Database::default();
+        for i in 0..5u128 {
+            Activity::record(&mut db, i % 2, Some(1), Target::Task(i), "edited", Some("before"), Some("after"));
+        }
+
+        let first = Activity::page(&db, |_| true, None, Some(2));
+        assert_eq!(first.entries.iter().map(|a| a.id).collect::<Vec<u64>>(), vec![5, 4]);
+        assert_eq!(first.next, Some(4));
+
+        let second = Activity::page(&db, |_| true, first.next, Some(2));
+        assert_eq!(second.entries.iter().map(|a| a.id).collect::<Vec<u64>>(), vec![3, 2]);
+
+        let last = Activity::page(&db, |_| true, Some(2), Some(2));
+        assert_eq!(last.entries.len(), 1);
+        assert_eq!(last.next, None);
+
+        let actor = Activity::page(&db, |a| a.actor == Some(1), None, None);
+        assert_eq!(actor.entries.iter().map(|a| a.id).collect::<Vec<u64>>(), vec![4, 2]);
+    }
+
+    #[test]
+    fn saves_append_new_entries_and_rewrite_replaced_logs() {
+        let mut db = Database::default();
+        for i in 0..3u128 {
+            Activity::record(&mut db, 1, Some(1), Target::Task(i), "edited", None::<()>, None::<()>);
+        }
+
+        let (append, contents) = Activity::pending(&db.activity, None);
+        assert!(!append);
+        assert_eq!(Activity::parse(&contents), (db.activity.clone(), true));
+
+        let written = db.activity.last().map(|a| (a.id, a.time));
+        assert_eq!(Activity::pending(&db.activity, written), (true, String::new()));
+        Activity::record(&mut db, 1, Some(1), Target::User(1), "password_changed", None::<()>, None::<()>);
+        let (append, contents) = Activity::pending(&db.activity, written);
+        assert!(append);
+        assert_eq!(Activity::parse(&contents).0.iter().map(|a| a.id).collect::<Vec<u64>>(), vec![4]);
+
+        // a restored snapshot doesn't continue what is on disk
+        db.activity.truncate(2);
+        let (append, contents) = Activity::pending(&db.activity, written);
+        assert!(!append);
+        assert_eq!(Activity::parse(&contents).0.len(), 2);
+
+        // a line cut off by a crash
+        let (entries, intact) = Activity::parse(&contents[..contents.len() - 10]);
+        assert_eq!((entries.len(), intact), (1, false));
+    }
+}
@@ -0,0 +1,247 @@
+use std::{collections::HashMap, path::PathBuf, sync::{Arc, RwLock}};
+
+use rocket::State;
+use serde::{Deserialize, Serialize};
+
+use crate::{access::{Access, Target}, activity::Activity, config, database::Database, login_info::{LoginInformation, LoginResult}, utils};
+
+pub const KEY_ID_MAX: u128 = 4294967296u128; // 16^8, 2^32
+pub const SECRET_LENGTH: usize = 40;
+pub const KEY_PREFIX: &str = "ath";
+// seconds, last_used is only moved forward once it is at least this old, so most requests don't need the write lock
+pub const LAST_USED_GRANULARITY: u128 = 60;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ApiKeyError {
+    // expires_in so large the expiry doesn't fit
+    ExpiryInvalid
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Scope {
+    pub read_only: bool,
+    pub project: Option<u128>
+}
+impl Scope {
+    pub fn allows(&self, db: &Database, access: Access, target: Target) -> bool {
+        if self.read_only && access != Access::Read {
+            return false;
+        }
+        match self.project {
+            Some(project_id) => target.project(db) == Some(project_id),
+            None => true
+        }
+    }
+}
+
+// only a hash of the secret is kept, the full key is shown once when it is minted
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApiKey {
+    pub user_id: u128,
+    pub name: String,
+    pub secret_hash: String,
+    pub scope: Scope,
+
+    pub created_at: u128,
+    pub expires_at: Option<u128>,
+    // accurate to LAST_USED_GRANULARITY
+    pub last_used: Option<u128>
+}
+impl ApiKey {
+    pub fn serialize(db: &Database) -> (PathBuf, String) {
+        (config::get().data_path("api_keys.json"), serde_json::to_string_pretty(&db.api_keys).unwrap())
+    }
+
+    pub fn load() -> HashMap<u128, ApiKey> {
+        utils::load_or_default(config::get().data_path("api_keys.json"))
+    }
+
+    // returns the key id and the full key, which is the only time the secret is available
+    pub fn create(db: &mut Database, user_id: u128, name: String, scope: Scope, expires_at: Option<u128>) -> (u128, String) {
+        let id = utils::generate_id(db.api_keys.keys().copied().collect::<Vec<u128>>(), KEY_ID_MAX);
+        let secret = utils::generate_token(SECRET_LENGTH);
+        db.api_keys.insert(id, ApiKey {
+            user_id,
+            name,
+            secret_hash: utils::hash_token(&secret),
+            scope,
+            created_at: utils::get_time(),
+            expires_at,
+            last_used: None
+        });
+        let after = ApiKey::list(db, user_id).into_iter().find(|k| k.id == id);
+        Activity::record(db, user_id, None, Target::User(user_id), "key_created", None::<()>, after);
+        db.save();
+        (id, format!("{KEY_PREFIX}_{id:x}_{secret}"))
+    }
+
+    // key id, if the key is one of ours and the secret matches
+    pub fn verify(db: &Database, key: &str) -> Result<u128, LoginResult> {
+        let mut parts = key.splitn(3, '_');
+        let (prefix, id, secret) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(p), Some(i), Some(s)) => (p, i, s),
+            _ => return Err(LoginResult::KeyInvalid)
+        };
+        let id = match u128::from_str_radix(id, 16) {
+            Ok(i) if prefix == KEY_PREFIX => i,
+            _ => return Err(LoginResult::KeyInvalid)
+        };
+
+        match db.api_keys.get(&id) {
+            Some(k) if k.secret_hash == utils::hash_token(secret) => {
+                if k.expires_at.is_some_and(|e| e <= utils::get_time()) {
+                    Err(LoginResult::KeyExpired)
+                } else {
+                    Ok(id)
+                }
+            },
+            _ => Err(LoginResult::KeyInvalid)
+        }
+    }
+
+    pub fn revoke(db: &mut Database, user_id: u128, key_id: u128) -> bool {
+        match db.api_keys.get(&key_id) {
+            Some(k) if k.user_id == user_id => {
+                db.api_keys.remove(&key_id);
+                Activity::record(db, user_id, None, Target::User(user_id), "key_revoked", Some(key_id), None::<()>);
+                db.save();
+                true
+            },
+            _ => false
+        }
+    }
+
+    pub fn list(db: &Database, user_id: u128) -> Vec<KeySummary> {
+        let mut result = db.api_keys.iter()
+            .filter(|(_, k)| k.user_id == user_id)
+            .map(|(i, k)| KeySummary {
+                id: *i,
+                name: k.name.clone(),
+                scope: k.scope,
+                created_at: k.created_at,
+                expires_at: k.expires_at,
+                last_used: k.last_used
+            })
+            .collect::<Vec<KeySummary>>();
+        result.sort_by_key(|k| (k.created_at, k.id));
+        result
+    }
+}
+
+// what listing shows, without the hash
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KeySummary {
+    pub id: u128,
+    pub name: String,
+    pub scope: Scope,
+    pub created_at: u128,
+    pub expires_at: Option<u128>,
+    // accurate to LAST_USED_GRANULARITY
+    pub last_used: Option<u128>
+}
+
+// #region api calls
+// keys can only be managed with a password login, never with another key
+#[post("/<name>?<read_only>&<project_id>&<expires_in>", data="<login>")]
+pub fn create(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, name: String, read_only: Option<bool>, project_id: Option<u128>, expires_in: Option<u128>) -> String {
+    if login.api_key.is_some() {
+        return utils::parse_response(Err(LoginResult::KeyNotAllowed));
+    }
+    let result = login.login(db);
+    match result {
+        LoginResult::Success(user_id) => {
+            let expires_at = match expires_in.map(|e| utils::get_time().checked_add(e)) {
+                Some(None) => return utils::parse_response(Err(ApiKeyError::ExpiryInvalid)),
+                e => e.flatten()
+            };
+            let mut db = db.write().unwrap();
+            let scope = Scope {
+                read_only: read_only.unwrap_or(false),
+                project: project_id
+            };
+            let (id, key) = ApiKey::create(&mut db, user_id, utils::decode_uri(name), scope, expires_at);
+            utils::parse_response(Ok((id, key)))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/", data="<login>")]
+pub fn list(db: &State<Arc<RwLock<Database>>>, login: LoginInformation) -> String {
+    if login.api_key.is_some() {
+        return utils::parse_response(Err(LoginResult::KeyNotAllowed));
+    }
+    let result = login.login(db);
+    match result {
+        LoginResult::Success(user_id) => {
+            let db = db.read().unwrap();
+            utils::parse_response(Ok(ApiKey::list(&db, user_id)))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/<key_id>", data="<login>")]
+pub fn revoke(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, key_id: u128) -> String {
+    if login.api_key.is_some() {
+        return utils::parse_response(Err(LoginResult::KeyNotAllowed));
+    }
+    let result = login.login(db);
+    match result {
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            utils::parse_response(Ok(ApiKey::revoke(&mut db, user_id, key_id)))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+// #endregion
+
+#[cfg(test)]
+mod tests {
+    use crate::{access::{Access, Target}, database::Database, login_info::LoginResult, utils};
+
+    use super::{ApiKey, Scope};
+
+    // built by hand rather than through create(), which would save to the data dir
+    fn insert(db: &mut Database, id: u128, secret: &str, scope: Scope, expires_at: Option<u128>) -> String {
+        db.api_keys.insert(id, ApiKey {
+            user_id: 7,
+            name: "test".to_string(),
+            secret_hash: utils::hash_token(secret),
+            scope,
+            created_at: 0,
+            expires_at,
+            last_used: None
+        });
+        format!("ath_{id:x}_{secret}")
+    }
+
+    #[test]
+    fn verifies_secret_and_expiry() {
+        let mut db = Database::default();
+        let key = insert(&mut db, 26, "secret", Scope::default(), None);
+        let expired = insert(&mut db, 27, "secret", Scope::default(), Some(utils::get_time() - 1));
+
+        assert_eq!(ApiKey::verify(&db, &key), Ok(26));
+        assert_eq!(ApiKey::verify(&db, &key.replace("secret", "guess")), Err(LoginResult::KeyInvalid));
+        assert_eq!(ApiKey::verify(&db, "ath_zz_secret"), Err(LoginResult::KeyInvalid));
+        assert_eq!(ApiKey::verify(&db, &expired), Err(LoginResult::KeyExpired));
+    }
+
+    #[test]
+    fn scope_limits_access_and_project() {
+        let mut db = Database::default();
+        db.index.group_project.insert(2, 1);
+        db.index.task_group.insert(3, 2);
+
+        let read_only = Scope { read_only: true, project: None };
+        assert!(read_only.allows(&db, Access::Read, Target::Global));
+        assert!(!read_only.allows(&db, Access::Write, Target::Task(3)));
+
+        let project = Scope { read_only: false, project: Some(1) };
+        assert!(project.allows(&db, Access::Write, Target::Task(3)));
+        assert!(!project.allows(&db, Access::Write, Target::Project(4)));
+        assert!(!project.allows(&db, Access::Read, Target::Global));
+    }
+}
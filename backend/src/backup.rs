@@ -0,0 +1,204 @@
+use std::{cmp::Reverse, fs, sync::{Arc, RwLock}, thread, time::Duration};
+
+use rocket::State;
+use serde::{Deserialize, Serialize};
+
+use crate::{access::Target, activity::Activity, config, database::Database, login_info::{LoginInformation, LoginResult}, utils};
+
+const SNAPSHOT_PREFIX: &str = "snapshot-";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Snapshot {
+    pub name: String,
+    pub timestamp: u128,
+    pub size: u64
+}
+impl Snapshot {
+    pub fn take(db: &Database) -> Option<Snapshot> {
+        let dir = &config::get().backup_dir;
+        fs::create_dir_all(dir).ok()?;
+
+        let timestamp = utils::get_time();
+        let name = format!("{SNAPSHOT_PREFIX}{timestamp}.json");
+        let contents = serde_json::to_string(db).ok()?;
+        let size = contents.len() as u64;
+        utils::write_atomic(dir.join(&name), contents).ok()?;
+
+        Some(Snapshot { name, timestamp, size })
+    }
+
+    // newest first
+    pub fn list() -> Vec<Snapshot> {
+        let mut result = match fs::read_dir(&config::get().backup_dir) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok())
+                .filter_map(|e| {
+                    let name = e.file_name().to_string_lossy().to_string();
+                    let timestamp = name.strip_prefix(SNAPSHOT_PREFIX)?.strip_suffix(".json")?.parse::<u128>().ok()?;
+                    let size = e.metadata().ok()?.len();
+                    Some(Snapshot { name, timestamp, size })
+                })
+                .collect::<Vec<Snapshot>>(),
+            Err(_) => vec![]
+        };
+        result.sort_by_key(|s| Reverse(s.timestamp));
+        result
+    }
+
+    pub fn read(name: &String) -> Option<Database> {
+        // only names produced by list() are accepted, which also keeps paths inside the backup dir
+        let snapshot = Snapshot::list().into_iter().find(|s| s.name == *name)?;
+        let contents = fs::read_to_string(config::get().backup_dir.join(snapshot.name)).ok()?;
+        serde_json::from_str(contents.as_str()).ok()
+    }
+
+    // the snapshot is fully parsed before anything is touched, so a bad file leaves the database as it was
+    // the activity log is the one thing that isn't rolled back, it keeps everything up to and including the restore
+    pub fn restore(db: &mut Database, actor: Option<u128>, name: &String) -> bool {
+        match Snapshot::read(name) {
+            Some(restored) => {
+                Snapshot::take(db);
+                let activity = std::mem::take(&mut db.activity);
+                *db = Database { activity, ..restored };
+                db.reindex();
+                db.backfill_stamps();
+                Activity::record(db, actor, None, Target::Global, "restored", None::<()>, Some(name));
+                db.save();
+                true
+            },
+            None => false
+        }
+    }
+
+    // the snapshots (newest first) that fall outside both the retention count and the max age, none when max_age is 0
+    pub fn expired(snapshots: Vec<Snapshot>, now: u128, retention: usize, max_age: u64) -> Vec<Snapshot> {
+        if max_age == 0 {
+            return vec![];
+        }
+        snapshots.into_iter()
+            .skip(retention)
+            .filter(|s| now.saturating_sub(s.timestamp) >= max_age as u128)
+            .collect()
+    }
+
+    pub fn prune() -> Vec<Snapshot> {
+        let config = config::get();
+        Snapshot::expired(Snapshot::list(), utils::get_time(), config.snapshot_retention, config.snapshot_max_age)
+            .into_iter()
+            .filter(|s| fs::remove_file(config.backup_dir.join(&s.name)).is_ok())
+            .collect()
+    }
+
+    pub fn schedule(db: Arc<RwLock<Database>>) {
+        let interval = config::get().snapshot_interval;
+        if interval == 0 {
+            return;
+        }
+
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(interval));
+            {
+                let db = db.read().unwrap();
+                if Snapshot::take(&db).is_none() {
+                    println!("failed to take scheduled snapshot");
+                }
+            }
+            Snapshot::prune();
+        });
+    }
+}
+
+// #region api calls
+#[post("/", data="<login>")]
+pub fn list(db: &State<Arc<RwLock<Database>>>, login: LoginInformation) -> String {
+    let result = login.login_admin(db);
+    match result {
+        LoginResult::Success(_) => utils::parse_response(Ok(Snapshot::list())),
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/", data="<login>")]
+pub fn take(db: &State<Arc<RwLock<Database>>>, login: LoginInformation) -> String {
+    let result = login.login_admin(db);
+    match result {
+        LoginResult::Success(_) => {
+            let db = db.read().unwrap();
+            match Snapshot::take(&db) {
+                Some(s) => utils::parse_response(Ok(s)),
+                None => utils::parse_response(Err(""))
+            }
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/<name>", data="<login>")]
+pub fn restore(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, name: String) -> String {
+    let result = login.login_admin(db);
+    match result {
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            if Snapshot::restore(&mut db, Some(user_id), &utils::decode_uri(name)) {
+                utils::parse_response(Ok("success"))
+            } else {
+                utils::parse_response(Err(""))
+            }
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+// #endregion
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs};
+
+    use crate::{access::Target, activity::Activity, config, database::Database, project::{Ownership, Project}};
+
+    use super::Snapshot;
+
+    fn snapshots(timestamps: &[u128]) -> Vec<Snapshot> {
+        timestamps.iter().map(|t| Snapshot { name: format!("snapshot-{t}.json"), timestamp: *t, size: 0 }).collect()
+    }
+
+    fn timestamps(snapshots: Vec<Snapshot>) -> Vec<u128> {
+        snapshots.into_iter().map(|s| s.timestamp).collect()
+    }
+
+    #[test]
+    fn prunes_only_old_snapshots_beyond_retention() {
+        let list = snapshots(&[1000, 900, 500, 100]);
+        // the newest two are kept whatever their age, of the rest only those at least 400s old go
+        assert_eq!(timestamps(Snapshot::expired(list.clone(), 1000, 2, 400)), vec![500, 100]);
+        assert_eq!(timestamps(Snapshot::expired(list.clone(), 1000, 2, 600)), vec![100]);
+        assert!(Snapshot::expired(list, 1000, 4, 1).is_empty());
+    }
+
+    #[test]
+    fn max_age_zero_keeps_everything() {
+        assert!(Snapshot::expired(snapshots(&[1000, 900, 500, 100]), 1000, 1, 0).is_empty());
+        assert!(Snapshot::expired(snapshots(&[1000, 900, 500, 100]), u128::MAX, 0, 0).is_empty());
+    }
+
+    #[test]
+    fn restores_keep_the_activity_log() {
+        let config = config::init_temp();
+        let mut db = Database::default();
+        let project = Project { name: "before".to_string(), owner: Ownership::User(1), groups: vec![], members: HashMap::new(), archived: false, offered_to: None, created_at: 1, created_by: Some(1), updated_at: 1, updated_by: Some(1) };
+        db.projects.insert(1, project.clone());
+        Activity::record(&mut db, 1, Some(1), Target::Project(1), "created", None::<()>, None::<()>);
+        // written by hand, take() names snapshots by the second and other tests take them too
+        fs::create_dir_all(&config.backup_dir).unwrap();
+        fs::write(config.backup_dir.join("snapshot-17.json"), serde_json::to_string(&db).unwrap()).unwrap();
+
+        db.projects.insert(1, Project { name: "after".to_string(), ..project });
+        Activity::record(&mut db, 2, Some(1), Target::Project(1), "edited", None::<()>, None::<()>);
+        assert!(Snapshot::restore(&mut db, Some(3), &"snapshot-17.json".to_string()));
+
+        assert_eq!(db.projects[&1].name, "before");
+        let log = db.activity.iter().map(|a| (a.actor, a.action.as_str())).collect::<Vec<(Option<u128>, &str)>>();
+        assert_eq!(log, vec![(Some(1), "created"), (Some(2), "edited"), (Some(3), "restored")]);
+        assert_eq!(db.activity[2].after, Some(serde_json::json!("snapshot-17.json")));
+    }
+}
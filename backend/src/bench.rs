@@ -0,0 +1,157 @@
+use std::{collections::HashMap, sync::{atomic::{AtomicBool, AtomicU64, Ordering}, Arc, Mutex, RwLock}, thread, time::{Duration, Instant}};
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{config::{self, Config}, database::Database, group::Group, project::{Ownership, Project}, task::{Species, Task}};
+
+const PROJECTS: u128 = 20;
+const GROUPS_PER_PROJECT: u128 = 5;
+const TASKS_PER_GROUP: u128 = 20;
+
+pub struct Workload {
+    pub readers: usize,
+    pub writers: usize,
+    pub duration: Duration
+}
+
+pub struct Throughput {
+    pub reads: u64,
+    pub writes: u64,
+    pub elapsed: Duration
+}
+impl Throughput {
+    pub fn print(&self, label: &str) {
+        let secs = self.elapsed.as_secs_f64();
+        println!(
+            "{label:<28} {:>10.0} reads/s {:>10.0} writes/s",
+            self.reads as f64 / secs,
+            self.writes as f64 / secs
+        );
+    }
+}
+
+// runs the same parallel read/write load twice against a throwaway data dir:
+// once the way the server used to work (one mutex, saves written to disk while it is held)
+// and once the way it works now (rwlock, saves handed to the background persister)
+pub fn run(workload: Workload) {
+    let dir = std::env::temp_dir().join(format!("athena-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    config::init(Config { data_dir: dir.clone(), ..Config::load(&Config::figment()) });
+
+    println!(
+        "{} readers, {} writers, {}s each, {} projects / {} tasks",
+        workload.readers,
+        workload.writers,
+        workload.duration.as_secs(),
+        PROJECTS,
+        PROJECTS * GROUPS_PER_PROJECT * TASKS_PER_GROUP
+    );
+
+    // the persister is process wide and can't be stopped again, so the mutex run has to go first
+    let db = Arc::new(Mutex::new(populate()));
+    run_workload(&workload, {
+        let db = db.clone();
+        move |p| Project::fetch(&db.lock().unwrap(), p, false).is_some()
+    }, {
+        let db = db.clone();
+        move |t| Task::edit(&mut db.lock().unwrap(), 0, t, "edited".to_string(), String::new())
+    }).print("mutex, synchronous saves");
+
+    let db = Arc::new(RwLock::new(populate()));
+    Database::persist(db.clone());
+    run_workload(&workload, {
+        let db = db.clone();
+        move |p| Project::fetch(&db.read().unwrap(), p, false).is_some()
+    }, {
+        let db = db.clone();
+        move |t| Task::edit(&mut db.write().unwrap(), 0, t, "edited".to_string(), String::new())
+    }).print("rwlock, background persister");
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+fn populate() -> Database {
+    let mut db = Database::default();
+    for p in 0..PROJECTS {
+        let groups = (0..GROUPS_PER_PROJECT).map(|g| p * GROUPS_PER_PROJECT + g).collect::<Vec<u128>>();
+        for g in &groups {
+            let tasks = (0..TASKS_PER_GROUP).map(|t| g * TASKS_PER_GROUP + t).collect::<Vec<u128>>();
+            for t in &tasks {
+                db.tasks.insert(*t, Task {
+                    id: *t,
+                    title: format!("task {t}"),
+                    description: String::new(),
+                    species: Species::Task(false),
+                    assigned: vec![],
+                    labels: vec![],
+                    due: None,
+                    created_at: 0,
+                    created_by: None,
+                    updated_at: 0,
+                    updated_by: None
+                });
+            }
+            db.groups.insert(*g, Group { name: format!("group {g}"), tasks, archived: false, created_at: 0, created_by: None, updated_at: 0, updated_by: None });
+        }
+        db.projects.insert(p, Project { name: format!("project {p}"), owner: Ownership::User(0), groups, members: HashMap::new(), archived: false, offered_to: None, created_at: 0, created_by: None, updated_at: 0, updated_by: None });
+    }
+    db.reindex();
+    db
+}
+
+fn run_workload<R, W>(workload: &Workload, read: R, write: W) -> Throughput
+where
+    R: Fn(u128) -> bool + Send + Clone + 'static,
+    W: Fn(u128) + Send + Clone + 'static
+{
+    let stop = Arc::new(AtomicBool::new(false));
+    let reads = Arc::new(AtomicU64::new(0));
+    let writes = Arc::new(AtomicU64::new(0));
+    let task_ids = (0..PROJECTS * GROUPS_PER_PROJECT * TASKS_PER_GROUP).collect::<Vec<u128>>();
+
+    let mut handles = vec![];
+    for _ in 0..workload.readers {
+        let (stop, reads, read) = (stop.clone(), reads.clone(), read.clone());
+        handles.push(thread::spawn(move || {
+            let mut rng = rand::thread_rng();
+            while !stop.load(Ordering::Relaxed) {
+                if read(rng.gen_range(0..PROJECTS)) {
+                    reads.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }));
+    }
+    for _ in 0..workload.writers {
+        let (stop, writes, write, task_ids) = (stop.clone(), writes.clone(), write.clone(), task_ids.clone());
+        handles.push(thread::spawn(move || {
+            let mut rng = rand::thread_rng();
+            while !stop.load(Ordering::Relaxed) {
+                write(*task_ids.choose(&mut rng).unwrap());
+                writes.fetch_add(1, Ordering::Relaxed);
+            }
+        }));
+    }
+
+    let start = Instant::now();
+    thread::sleep(workload.duration);
+    stop.store(true, Ordering::Relaxed);
+    for h in handles {
+        h.join().unwrap();
+    }
+
+    Throughput {
+        reads: reads.load(Ordering::Relaxed),
+        writes: writes.load(Ordering::Relaxed),
+        elapsed: start.elapsed()
+    }
+}
+
+// backend bench [readers] [writers] [seconds]
+pub fn parse_args(args: &[&str]) -> Workload {
+    let arg = |i: usize, default: u64| args.get(i).and_then(|a| a.parse::<u64>().ok()).unwrap_or(default);
+    Workload {
+        readers: arg(0, 8) as usize,
+        writers: arg(1, 2) as usize,
+        duration: Duration::from_secs(arg(2, 3))
+    }
+}
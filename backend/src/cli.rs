@@ -0,0 +1,54 @@
+use crate::{backup::Snapshot, bench, database::Database, fsck};
+
+// offline maintenance commands, run as `backend <command> [args]`
+// these work on the files in the data dir directly, a running server has to /load afterwards to see the changes
+pub fn run(args: &[String]) {
+    match args.iter().map(|a| a.as_str()).collect::<Vec<&str>>().as_slice() {
+        ["snapshot", "list"] => {
+            for s in Snapshot::list() {
+                println!("{}\t{}\t{} bytes", s.name, s.timestamp, s.size);
+            }
+        },
+        ["snapshot", "take"] => {
+            match Snapshot::take(&Database::load()) {
+                Some(s) => println!("took {}", s.name),
+                None => println!("failed to take snapshot")
+            }
+        },
+        ["snapshot", "restore", name] => {
+            let mut db = Database::load();
+            if Snapshot::restore(&mut db, None, &name.to_string()) {
+                println!("restored {name}");
+            } else {
+                println!("no such snapshot {name}");
+            }
+        },
+        ["snapshot", "prune"] => {
+            for s in Snapshot::prune() {
+                println!("removed {}", s.name);
+            }
+        },
+        ["fsck"] => {
+            fsck::print_report(&fsck::check(&Database::load()), false);
+        },
+        ["fsck", "--repair"] => {
+            fsck::print_report(&fsck::repair(&mut Database::load()), true);
+        },
+        ["bench", rest @ ..] => {
+            bench::run(bench::parse_args(rest));
+        },
+        _ => usage()
+    }
+}
+
+fn usage() {
+    println!("usage:");
+    println!("    backend                              start the server");
+    println!("    backend snapshot list                list snapshots, newest first");
+    println!("    backend snapshot take                snapshot the data dir");
+    println!("    backend snapshot restore <name>      restore a snapshot into the data dir");
+    println!("    backend snapshot prune               apply the retention rules");
+    println!("    backend fsck [--repair]              check the data dir for broken references");
+    println!("    backend bench [readers] [writers] [seconds]");
+    println!("                                         measure throughput under parallel read/write load");
+}
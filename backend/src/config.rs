@@ -0,0 +1,166 @@
+use std::{net::{IpAddr, Ipv4Addr}, path::PathBuf, sync::OnceLock};
+
+use rocket::figment::{providers::{Env, Format, Serialized, Toml}, Figment, Profile};
+use serde::{Deserialize, Serialize};
+
+use crate::{account::RegistrationMode, fsck::FsckMode, identity::ProviderKind};
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+pub const PRODUCTION_PROFILE: &str = "production";
+
+// layered as: rocket defaults -> athena defaults -> Athena.toml (or $ATHENA_CONFIG) -> ATHENA_* env
+// the same figment is handed to rocket, so address/port/tls are picked up by rocket itself
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Config {
+    pub address: IpAddr,
+    pub port: u16,
+
+    pub data_dir: PathBuf,
+    pub soterius_path: PathBuf,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+
+    // origins allowed to call the api from a browser, "*" allows any origin but then no credentials are sent along
+    pub cors_origins: Vec<String>,
+    pub cors_max_age: u64, // seconds browsers may cache a preflight answer
+
+    // usernames that are always server admins, on top of users given the Admin role
+    pub admins: Vec<String>,
+    // /save, /load and /debug, off by default in the production profile
+    pub admin_routes: bool,
+
+    pub backup_dir: PathBuf,
+    pub snapshot_interval: u64, // seconds between scheduled snapshots, 0 disables the scheduler
+    pub snapshot_retention: usize, // newest n snapshots are always kept
+    pub snapshot_max_age: u64, // seconds, older snapshots beyond the retention count are pruned; 0 keeps them forever
+
+    // where logins are checked: "soterius", "local" (data_dir/credentials.json) or "ldap"
+    pub identity_provider: ProviderKind,
+    pub ldap_address: String, // "ldaps://host[:port]", or "ldap://host[:port]" / "host:port" without tls
+    // allow binds over plain ldap, which sends passwords in the clear, off by default in the production profile
+    pub ldap_plaintext: bool,
+    pub ldap_user_dn: String, // {username} is replaced with the escaped username
+
+    // who may create accounts through /account/register
+    pub registration: RegistrationMode,
+
+    // login throttling, see throttle.rs
+    pub login_free_attempts: u32,
+    pub login_backoff_base: u64, // seconds, doubled for every failure past the free attempts
+    pub login_max_backoff: u64,
+    pub login_lockout_threshold: u32, // failures per username before a full lockout
+    pub login_ip_lockout_threshold: u32, // failures per client ip before a full lockout
+    pub login_lockout: u64, // seconds
+    // answer both unknown usernames and wrong passwords with InvalidCredentials, so usernames can't be probed
+    pub generic_login_failure: bool,
+    // seconds a session token from /2fa/session stands in for an otp code
+    pub second_factor_session: u64,
+
+    // outgoing webhooks, see webhook.rs
+    pub webhook_max_attempts: u32,
+    pub webhook_backoff_base: u64, // seconds before the first retry, doubled for every one after it
+    pub webhook_timeout: u64, // seconds per delivery attempt
+    pub webhook_log_size: usize, // deliveries kept per webhook
+    // hosts webhooks may reach even though they resolve to loopback, private or link-local addresses
+    pub webhook_allowed_hosts: Vec<String>,
+
+    // seconds deleted projects, groups and tasks stay restorable, 0 keeps them forever
+    pub trash_retention: u64,
+
+    // integrity check run on startup: "off", "check" (report only) or "repair"
+    pub fsck: FsckMode
+}
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 8002,
+            data_dir: PathBuf::from("data"),
+            soterius_path: PathBuf::from("../../data/users.json"),
+            tls: None,
+            cors_origins: vec!["*".to_string()],
+            cors_max_age: 86400,
+            admins: vec![],
+            admin_routes: true,
+            backup_dir: PathBuf::from("backups"),
+            snapshot_interval: 3600,
+            snapshot_retention: 24,
+            snapshot_max_age: 604800,
+            identity_provider: ProviderKind::Soterius,
+            ldap_address: "127.0.0.1:389".to_string(),
+            ldap_plaintext: true,
+            ldap_user_dn: "uid={username},ou=people,dc=example,dc=org".to_string(),
+            registration: RegistrationMode::Closed,
+            login_free_attempts: 3,
+            login_backoff_base: 1,
+            login_max_backoff: 60,
+            login_lockout_threshold: 10,
+            login_ip_lockout_threshold: 50,
+            login_lockout: 900,
+            generic_login_failure: false,
+            second_factor_session: 3600,
+            webhook_max_attempts: 6,
+            webhook_backoff_base: 10,
+            webhook_timeout: 10,
+            webhook_log_size: 50,
+            webhook_allowed_hosts: vec![],
+            trash_retention: 2592000,
+            fsck: FsckMode::Check
+        }
+    }
+}
+impl Config {
+    pub fn figment() -> Figment {
+        Figment::from(rocket::Config::default())
+            .merge(Serialized::defaults(Config::default()))
+            .merge(Serialized::default("admin_routes", false).profile(PRODUCTION_PROFILE))
+            .merge(Serialized::default("ldap_plaintext", false).profile(PRODUCTION_PROFILE))
+            .merge(Toml::file(Env::var_or("ATHENA_CONFIG", "Athena.toml")).nested())
+            .merge(Env::prefixed("ATHENA_").ignore(&["CONFIG", "PROFILE"]).global())
+            .select(Profile::from_env_or("ATHENA_PROFILE", rocket::Config::DEFAULT_PROFILE))
+    }
+
+    pub fn profile() -> String {
+        Config::figment().profile().to_string()
+    }
+
+    pub fn load(figment: &Figment) -> Config {
+        match figment.extract::<Config>() {
+            Ok(c) => c,
+            Err(e) => panic!("invalid configuration: {e}")
+        }
+    }
+
+    pub fn data_path(&self, file: &str) -> PathBuf {
+        self.data_dir.join(file)
+    }
+
+    pub fn is_admin(&self, username: &String) -> bool {
+        self.admins.contains(username)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TlsConfig {
+    pub certs: PathBuf,
+    pub key: PathBuf
+}
+
+// only takes effect before the first get(), afterwards the loaded config stays
+pub fn init(config: Config) -> &'static Config {
+    CONFIG.get_or_init(|| config)
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(|| Config::load(&Config::figment()))
+}
+
+// for tests that end up saving, data and snapshots go to a temp dir shared by the whole test run
+#[cfg(test)]
+pub fn init_temp() -> &'static Config {
+    let dir = std::env::temp_dir().join(format!("athena-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    init(Config { data_dir: dir.clone(), backup_dir: dir.join("backups"), ..Config::default() })
+}
@@ -1,11 +1,44 @@
-use rocket::http::{Header, Status};
+use rocket::http::{Header, Method, Status};
 use rocket::{Request, Response};
 use rocket::fairing::{Fairing, Info, Kind};
 
-pub struct CORS;
+use crate::config;
+
+pub const ALLOWED_METHODS: &str = "GET, POST, PATCH, DELETE, OPTIONS";
+pub const ALLOWED_HEADERS: &str = "Content-Type, Authorization";
+
+pub struct Cors;
+
+// what goes into Access-Control-Allow-Origin for a request's Origin
+#[derive(Debug, PartialEq, Eq)]
+pub enum AllowedOrigin {
+    Any,
+    Exact(String)
+}
+impl AllowedOrigin {
+    // None if the origin isn't allowed, an exact match wins over "*", so listed origins keep their credentials even with the wildcard configured
+    pub fn matching(origins: &[String], origin: Option<&str>) -> Option<AllowedOrigin> {
+        match origin {
+            Some(o) if origins.iter().any(|i| i == o) => Some(AllowedOrigin::Exact(o.to_string())),
+            _ if origins.iter().any(|i| i == "*") => Some(AllowedOrigin::Any),
+            _ => None
+        }
+    }
+
+    // browsers refuse credentials together with "*", so only exact matches get them
+    pub fn headers(self) -> Vec<Header<'static>> {
+        match self {
+            AllowedOrigin::Any => vec![Header::new("Access-Control-Allow-Origin", "*")],
+            AllowedOrigin::Exact(origin) => vec![
+                Header::new("Access-Control-Allow-Origin", origin),
+                Header::new("Access-Control-Allow-Credentials", "true")
+            ]
+        }
+    }
+}
 
 #[rocket::async_trait]
-impl Fairing for CORS {
+impl Fairing for Cors {
     fn info(&self) -> Info {
         Info {
             name: "cors headers",
@@ -13,12 +46,67 @@ impl Fairing for CORS {
         }
     }
 
-    async fn on_response<'r>(&self, _request: &'r Request<'_>, response: &mut Response<'r>) {
-        response.set_status(Status::Ok); // required for post protocol to overcome "preflight request" error thingy
-        // 
-        response.set_header(Header::new("Access-Control-Allow-Origin", "*"));
-        response.set_header(Header::new("Access-Control-Allow-Methods", "POST, GET, PATCH, OPTIONS"));
-        response.set_header(Header::new("Access-Control-Allow-Headers", "*"));
-        response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let preflight = request.method() == Method::Options;
+        if preflight {
+            response.set_status(Status::Ok); // required for post protocol to overcome "preflight request" error thingy
+        }
+
+        let config = config::get();
+        // the answer depends on the Origin header, so caches must not hand it to other origins
+        response.set_header(Header::new("Vary", "Origin"));
+        match AllowedOrigin::matching(&config.cors_origins, request.headers().get_one("Origin")) {
+            Some(allowed) => {
+                for header in allowed.headers() {
+                    response.set_header(header);
+                }
+            },
+            None => return
+        }
+
+        if preflight {
+            response.set_header(Header::new("Access-Control-Allow-Methods", ALLOWED_METHODS));
+            response.set_header(Header::new("Access-Control-Allow-Headers", ALLOWED_HEADERS));
+            response.set_header(Header::new("Access-Control-Max-Age", config.cors_max_age.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AllowedOrigin;
+
+    fn origins(list: &[&str]) -> Vec<String> {
+        list.iter().map(|o| o.to_string()).collect()
+    }
+
+    fn headers(allowed: AllowedOrigin) -> Vec<(String, String)> {
+        allowed.headers().into_iter().map(|h| (h.name().to_string(), h.value().to_string())).collect()
+    }
+
+    #[test]
+    fn exact_origins_win_over_the_wildcard() {
+        let listed = origins(&["https://app.example.org", "https://admin.example.org"]);
+        assert_eq!(AllowedOrigin::matching(&listed, Some("https://app.example.org")), Some(AllowedOrigin::Exact("https://app.example.org".to_string())));
+        // no prefix, suffix or case games
+        assert_eq!(AllowedOrigin::matching(&listed, Some("https://app.example.org.evil.com")), None);
+        assert_eq!(AllowedOrigin::matching(&listed, Some("https://APP.example.org")), None);
+        assert_eq!(AllowedOrigin::matching(&listed, Some("http://app.example.org")), None);
+        assert_eq!(AllowedOrigin::matching(&listed, None), None);
+
+        let wildcard = origins(&["https://app.example.org", "*"]);
+        assert_eq!(AllowedOrigin::matching(&wildcard, Some("https://app.example.org")), Some(AllowedOrigin::Exact("https://app.example.org".to_string())));
+        assert_eq!(AllowedOrigin::matching(&wildcard, Some("https://elsewhere.example.com")), Some(AllowedOrigin::Any));
+        assert_eq!(AllowedOrigin::matching(&wildcard, None), Some(AllowedOrigin::Any));
+        assert_eq!(AllowedOrigin::matching(&[], Some("https://app.example.org")), None);
+    }
+
+    #[test]
+    fn credentials_only_for_exact_matches() {
+        assert_eq!(headers(AllowedOrigin::Any), vec![("Access-Control-Allow-Origin".to_string(), "*".to_string())]);
+        assert_eq!(headers(AllowedOrigin::Exact("https://app.example.org".to_string())), vec![
+            ("Access-Control-Allow-Origin".to_string(), "https://app.example.org".to_string()),
+            ("Access-Control-Allow-Credentials".to_string(), "true".to_string())
+        ]);
     }
-}
\ No newline at end of file
+}
@@ -1,69 +1,319 @@
-use std::{collections::HashMap, sync::Mutex};
+use std::{collections::{HashMap, VecDeque}, path::PathBuf, sync::{mpsc::{self, Sender}, Arc, OnceLock, RwLock}, thread};
 
 use rocket::State;
 use serde::{Deserialize, Serialize};
 
-use crate::{group::Group, project::Project, task::Task, team::Team, user::User};
+use crate::{access::Target, account::Invite, activity::Activity, apikey::ApiKey, backup::Snapshot, config::{self, Config}, filter::SavedFilter, fsck, group::Group, login_info::{LoginInformation, LoginResult}, indices::Index, project::Project, search::SearchIndex, share::ShareLink, task::Task, team::Team, totp::SecondFactor, trash::{Trash, Trashed}, user::{Role, User}, utils, webhook::{Delivery, Job, Webhook}};
 
-#[derive(Serialize, Deserialize)]
+static PERSISTER: OnceLock<Sender<()>> = OnceLock::new();
+
+#[derive(Serialize, Deserialize, Default)]
 pub struct Database {
     pub users: HashMap<u128, User>,
     pub teams: HashMap<u128, Team>,
 
     pub projects: HashMap<u128, Project>,
     pub groups: HashMap<u128, Group>,
-    pub tasks: HashMap<u128, Task>
+    pub tasks: HashMap<u128, Task>,
+
+    #[serde(default)]
+    pub invites: HashMap<String, Invite>,
+    #[serde(default)]
+    pub api_keys: HashMap<u128, ApiKey>,
+    #[serde(default)]
+    pub second_factors: HashMap<u128, SecondFactor>,
+    #[serde(default)]
+    pub share_links: HashMap<u128, ShareLink>,
+    #[serde(default)]
+    pub webhooks: HashMap<u128, Webhook>,
+    #[serde(default)]
+    pub webhook_deliveries: HashMap<u128, VecDeque<Delivery>>,
+    #[serde(default)]
+    pub webhook_queue: Vec<Job>,
+    #[serde(default)]
+    pub activity: Vec<Activity>,
+    #[serde(default)]
+    pub trash: HashMap<u128, Trashed>,
+    #[serde(default)]
+    pub saved_filters: HashMap<u128, SavedFilter>,
+
+    #[serde(skip)]
+    pub index: Index,
+    #[serde(skip)]
+    pub search: SearchIndex
 }
 impl Database {
+    // queues a write for the persister when it is running (the server), otherwise writes straight away (cli, tests)
     pub fn save(&self) {
-        User::save(&self);
-        Team::save(&self);
-        
-        Project::save(&self);
-        Group::save(&self);
-        Task::save(&self);
+        match PERSISTER.get() {
+            Some(p) => {
+                let _ = p.send(());
+            },
+            None => self.save_now()
+        }
+    }
+
+    pub fn save_now(&self) {
+        let activity = Activity::serialize(self);
+        Database::write(self.serialize());
+        activity.write();
+    }
+
+    pub fn serialize(&self) -> Vec<(PathBuf, String)> {
+        let mut result = vec![
+            User::serialize(self),
+            Team::serialize(self),
+
+            Project::serialize(self),
+            Group::serialize(self),
+            Task::serialize(self),
+
+            Invite::serialize(self),
+            ApiKey::serialize(self),
+            SecondFactor::serialize(self),
+            ShareLink::serialize(self),
+            Trash::serialize(self),
+            SavedFilter::serialize(self)
+        ];
+        result.extend(Webhook::serialize(self));
+        result
+    }
+
+    fn write(files: Vec<(PathBuf, String)>) {
+        for (path, contents) in files {
+            if let Err(e) = utils::write_atomic(&path, contents) {
+                println!("failed to write {}: {e}", path.display());
+            }
+        }
+    }
+
+    // moves disk writes off the request path: routes only queue a save, this thread serializes under a
+    // read lock (so readers carry on) and does the file I/O with no lock held at all
+    pub fn persist(db: Arc<RwLock<Database>>) {
+        let (sender, receiver) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            while receiver.recv().is_ok() {
+                // saves queued while the last write was running collapse into one
+                while receiver.try_recv().is_ok() {}
+                let (files, activity) = {
+                    let db = db.read().unwrap();
+                    (db.serialize(), Activity::serialize(&db))
+                };
+                Database::write(files);
+                activity.write();
+            }
+        });
+        let _ = PERSISTER.set(sender);
     }
 
     pub fn load() -> Database {
-        let result = Database {
+        let mut result = Database {
             users: User::load(),
             teams: Team::load(),
             projects: Project::load(),
             groups: Group::load(),
-            tasks: Task::load()
+            tasks: Task::load(),
+            invites: Invite::load(),
+            api_keys: ApiKey::load(),
+            second_factors: SecondFactor::load(),
+            share_links: ShareLink::load(),
+            webhooks: Webhook::load(),
+            webhook_deliveries: Webhook::load_deliveries(),
+            webhook_queue: Webhook::load_queue(),
+            activity: Activity::load(),
+            trash: Trash::load(),
+            saved_filters: SavedFilter::load(),
+            index: Index::default(),
+            search: SearchIndex::default()
         };
+        result.reindex();
+        if result.backfill_stamps() {
+            result.save_now();
+        }
 
         result
     }
 
-    pub fn fetch_user_id(&self, username: &String) -> Option<u128> {
-        for (i, u) in &self.users {
-            if *username == u.username {
-                return Some(*i);
+    // projects, groups and tasks from before creation and changes were tracked have no stamps yet. the creation
+    // stamp only comes from a "created" entry, the update stamp from the last entry or else the time of the
+    // migration, authors nobody logged stay None. true if anything changed
+    pub fn backfill_stamps(&mut self) -> bool {
+        // time and actor
+        let mut created: HashMap<Target, (u128, Option<u128>)> = HashMap::new();
+        let mut updated: HashMap<Target, (u128, Option<u128>)> = HashMap::new();
+        for a in &self.activity {
+            if a.action == "created" {
+                created.insert(a.entity, (a.time, a.actor));
+            }
+            updated.insert(a.entity, (a.time, a.actor));
+        }
+        let now = utils::get_time();
+        let stamps = |entity: Target| {
+            let (created_at, created_by) = created.get(&entity).copied().unwrap_or((0, None));
+            let (updated_at, updated_by) = updated.get(&entity).copied().unwrap_or((now, None));
+            (created_at, created_by, updated_at, updated_by)
+        };
+
+        let mut changed = false;
+        let trashed_projects = self.trash.values_mut().filter_map(|t| t.project.as_mut().map(|p| (t.project_id, p)));
+        for (id, p) in self.projects.iter_mut().map(|(i, p)| (*i, p)).chain(trashed_projects) {
+            if p.updated_at == 0 {
+                (p.created_at, p.created_by, p.updated_at, p.updated_by) = stamps(Target::Project(id));
+                changed = true;
+            }
+        }
+        let trashed_groups = self.trash.values_mut().flat_map(|t| t.groups.iter_mut().map(|(i, g)| (*i, g)));
+        for (id, g) in self.groups.iter_mut().map(|(i, g)| (*i, g)).chain(trashed_groups) {
+            if g.updated_at == 0 {
+                (g.created_at, g.created_by, g.updated_at, g.updated_by) = stamps(Target::Group(id));
+                changed = true;
+            }
+        }
+        let trashed_tasks = self.trash.values_mut().flat_map(|t| t.tasks.iter_mut().map(|(i, t)| (*i, t)));
+        for (id, t) in self.tasks.iter_mut().map(|(i, t)| (*i, t)).chain(trashed_tasks) {
+            if t.updated_at == 0 {
+                (t.created_at, t.created_by, t.updated_at, t.updated_by) = stamps(Target::Task(id));
+                changed = true;
             }
         }
-        None
+        changed
+    }
+
+    // has to be called after the maps are changed wholesale (restoring a snapshot, fsck repairs)
+    pub fn reindex(&mut self) {
+        self.index = Index::build(self);
+        self.search = SearchIndex::build(self);
+    }
+
+    pub fn fetch_user_id(&self, username: &String) -> Option<u128> {
+        self.index.username_id.get(username).copied()
+    }
+
+    pub fn insert_user(&mut self, user_id: u128, username: String) {
+        self.index.username_id.insert(username.clone(), user_id);
+        self.users.insert(user_id, User {
+            id: user_id,
+            username,
+            role: Role::Member
+        });
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Diagnostics {
+    pub version: String,
+    pub profile: String,
+    pub data_dir: PathBuf,
+
+    pub users: usize,
+    pub admins: usize,
+    pub teams: usize,
+    pub projects: usize,
+    pub groups: usize,
+    pub tasks: usize,
+
+    pub index_consistent: bool,
+    pub issues: Vec<String>,
+
+    pub snapshots: usize,
+    pub latest_snapshot: Option<Snapshot>
+}
+impl Diagnostics {
+    pub fn collect(db: &Database) -> Diagnostics {
+        let config = config::get();
+        let snapshots = Snapshot::list();
+        Diagnostics {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            profile: Config::profile(),
+            data_dir: config.data_dir.clone(),
+
+            users: db.users.len(),
+            admins: db.users.values().filter(|u| u.role == Role::Admin || config.is_admin(&u.username)).count(),
+            teams: db.teams.len(),
+            projects: db.projects.len(),
+            groups: db.groups.len(),
+            tasks: db.tasks.len(),
+
+            index_consistent: db.index == Index::build(db) && db.search == SearchIndex::build(db),
+            issues: fsck::check(db).iter().map(|i| i.describe()).collect::<Vec<String>>(),
+
+            snapshots: snapshots.len(),
+            latest_snapshot: snapshots.first().cloned()
+        }
     }
 }
 
 // #region api calls
-#[get("/")]
-pub fn save(db: &State<Mutex<Database>>) -> String {
-    let db = db.lock().unwrap();
-    db.save();
-    "success".to_string()
+// only mounted when admin_routes is on, see main
+#[post("/", data="<login>")]
+pub fn save(db: &State<Arc<RwLock<Database>>>, login: LoginInformation) -> String {
+    let result = login.login_admin(db);
+    match result {
+        LoginResult::Success(_) => {
+            let db = db.read().unwrap();
+            db.save();
+            utils::parse_response(Ok("success"))
+        },
+        _ => utils::parse_response(Err(result))
+    }
 }
 
-#[get("/")]
-pub fn load(db: &State<Mutex<Database>>) -> String {
-    let mut db = db.lock().unwrap();
-    *db = Database::load();
-    "success".to_string()
+#[post("/", data="<login>")]
+pub fn load(db: &State<Arc<RwLock<Database>>>, login: LoginInformation) -> String {
+    let result = login.login_admin(db);
+    match result {
+        LoginResult::Success(_) => {
+            let loaded = Database::load();
+            *db.write().unwrap() = loaded;
+            utils::parse_response(Ok("success"))
+        },
+        _ => utils::parse_response(Err(result))
+    }
 }
 
-#[get("/")]
-pub fn debug(db: &State<Mutex<Database>>) -> String {
-    let db = db.lock().unwrap();
-    serde_json::to_string_pretty(&db.users).unwrap()
+#[post("/", data="<login>")]
+pub fn debug(db: &State<Arc<RwLock<Database>>>, login: LoginInformation) -> String {
+    let result = login.login_admin(db);
+    match result {
+        LoginResult::Success(_) => {
+            let db = db.read().unwrap();
+            utils::parse_response(Ok(Diagnostics::collect(&db)))
+        },
+        _ => utils::parse_response(Err(result))
+    }
 }
 // #endregion
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{access::Target, activity::Activity, project::{Ownership, Project}};
+
+    use super::Database;
+
+    #[test]
+    fn backfills_stamps_from_activity() {
+        let mut db = Database::default();
+        let project = |name: &str| Project { name: name.to_string(), owner: Ownership::User(1), groups: vec![], members: HashMap::new(), archived: false, offered_to: None, created_at: 0, created_by: None, updated_at: 0, updated_by: None };
+        db.projects.insert(1, project("logged"));
+        db.projects.insert(2, project("edited"));
+        db.projects.insert(3, project("older"));
+        Activity::record(&mut db, 7, Some(1), Target::Project(1), "created", None::<()>, None::<()>);
+        Activity::record(&mut db, 8, Some(1), Target::Project(1), "edited", None::<()>, None::<()>);
+        Activity::record(&mut db, 9, Some(2), Target::Project(2), "edited", None::<()>, None::<()>);
+        let (first, last) = (db.activity[0].time, db.activity[1].time);
+
+        assert!(db.backfill_stamps());
+        let logged = &db.projects[&1];
+        assert_eq!((logged.created_at, logged.created_by, logged.updated_at, logged.updated_by), (first, Some(7), last, Some(8)));
+        // an edit says nothing about who created it
+        let edited = &db.projects[&2];
+        assert_eq!((edited.created_at, edited.created_by, edited.updated_by), (0, None, Some(9)));
+        let older = &db.projects[&3];
+        assert!(older.updated_at >= last);
+        assert_eq!((older.created_at, older.created_by, older.updated_by), (0, None, None));
+        // nothing left to do the second time
+        assert!(!db.backfill_stamps());
+    }
+}
@@ -0,0 +1,211 @@
+use std::sync::{Arc, OnceLock, RwLock};
+
+use rocket::{response::stream::{Event as StreamEvent, EventStream}, tokio::{select, sync::broadcast::{self, error::RecvError, Sender}, task}, Shutdown, State};
+use serde::{Deserialize, Serialize};
+
+use crate::{access::{self, Access, Target}, apikey::ApiKey, database::Database, group::Group, login_info::{LoginInformation, LoginResult}, project::Ownership, task::{Species, Task}, team::Permissions, utils, webhook::Webhook};
+
+// events a slow subscriber may fall behind by before it starts missing some
+pub const CAPACITY: usize = 256;
+
+static BUS: OnceLock<Sender<Event>> = OnceLock::new();
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Event {
+    pub project_id: u128,
+    pub time: u128,
+    pub change: Change
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type")]
+pub enum Change {
+    ProjectEdited { name: String },
+    ProjectDeleted,
+    ProjectTransferred { owner: Ownership },
+    ProjectRestored,
+    ProjectArchived { archived: bool },
+    MemberChanged { user_id: u128, permissions: Option<Permissions> },
+
+    GroupCreated { group_id: u128, name: String },
+    GroupEdited { group_id: u128, name: String },
+    GroupDeleted { group_id: u128 },
+    GroupRestored { group_id: u128, position: usize, group: Group },
+    GroupArchived { group_id: u128, archived: bool },
+
+    TaskCreated { group_id: u128, task: Task },
+    TaskEdited { task_id: u128, title: String, description: String },
+    TaskDeleted { task_id: u128 },
+    TaskRestored { group_id: u128, position: usize, task: Task },
+    TaskCompleted { task_id: u128, species: Species },
+    TaskAssigned { task_id: u128, assigned: Vec<u128> },
+    TaskLabelled { task_id: u128, labels: Vec<String> },
+    TaskDueChanged { task_id: u128, due: Option<u128> },
+
+    // never emitted, only sent by webhook test fires
+    Ping
+}
+impl Change {
+    // the serde tag, which is what webhook filters match on
+    pub fn kind(&self) -> String {
+        serde_json::to_value(self).ok()
+            .and_then(|v| v.get("type").and_then(|t| t.as_str()).map(|t| t.to_string()))
+            .unwrap_or_default()
+    }
+}
+
+// every Change type, for validating webhook filters
+pub const KINDS: &[&str] = &[
+    "ProjectEdited", "ProjectDeleted", "ProjectTransferred", "ProjectRestored", "ProjectArchived", "MemberChanged",
+    "GroupCreated", "GroupEdited", "GroupDeleted", "GroupRestored", "GroupArchived",
+    "TaskCreated", "TaskEdited", "TaskDeleted", "TaskRestored", "TaskCompleted", "TaskAssigned", "TaskLabelled", "TaskDueChanged",
+    "Ping"
+];
+
+pub fn bus() -> &'static Sender<Event> {
+    BUS.get_or_init(|| broadcast::channel(CAPACITY).0)
+}
+
+// called by the mutating functions once the change is made, None for things that don't belong to a project
+// webhook jobs are queued right here, so unlike stream subscribers they can't fall behind and miss events
+pub fn emit(db: &mut Database, project_id: Option<u128>, change: Change) {
+    if let Some(project_id) = project_id {
+        let event = Event {
+            project_id,
+            time: utils::get_time(),
+            change
+        };
+        Webhook::enqueue(db, &event);
+        // fails when nobody is listening, which is fine
+        let _ = bus().send(event);
+    }
+}
+
+// what a subscription does with an event for its project
+#[derive(Debug, PartialEq, Eq)]
+pub enum Delivery {
+    Send,
+    // the project is gone, the event goes out and the stream ends
+    SendLast,
+    // access was lost since subscribing, nothing more goes out
+    End
+}
+
+#[derive(Clone)]
+pub struct Subscriber {
+    pub user_id: u128,
+    pub project_id: u128,
+    // rechecked with every event, so revoking or expiring the key ends the stream too
+    pub api_key: Option<String>
+}
+impl Subscriber {
+    pub fn delivery(&self, db: &Database, change: &Change) -> Delivery {
+        let deleted = matches!(change, Change::ProjectDeleted);
+        let key_valid = match &self.api_key {
+            Some(key) => ApiKey::verify(db, key).ok().and_then(|i| db.api_keys.get(&i))
+                .is_some_and(|k| k.user_id == self.user_id && k.scope.project.is_none_or(|p| p == self.project_id)),
+            None => true
+        };
+        if !key_valid {
+            Delivery::End
+        } else if deleted {
+            Delivery::SendLast
+        } else if access::project_permissions(db, self.user_id, self.project_id).allows(Permissions::Viewer) {
+            Delivery::Send
+        } else {
+            Delivery::End
+        }
+    }
+}
+
+// #region api calls
+// server-sent events for one project, permissions and the api key are checked again for every event so losing access ends the stream
+#[post("/<project_id>", data="<login>")]
+pub fn subscribe(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, project_id: u128, mut shutdown: Shutdown) -> Result<EventStream![], String> {
+    let result = login.authorize(db, Access::Read, Target::Project(project_id));
+    let user_id = match result {
+        LoginResult::Success(user_id) => user_id,
+        _ => return Err(utils::parse_response(Err(result)))
+    };
+
+    let subscriber = Subscriber { user_id, project_id, api_key: login.api_key.clone() };
+    let mut receiver = bus().subscribe();
+    let db = db.inner().clone();
+    Ok(EventStream! {
+        loop {
+            let event = select! {
+                e = receiver.recv() => e,
+                _ = &mut shutdown => break
+            };
+            match event {
+                Ok(e) if e.project_id == project_id => {
+                    // the emitter may still hold the write lock, so the check waits off the async workers
+                    let (db, subscriber, change) = (db.clone(), subscriber.clone(), e.change.clone());
+                    let delivery = task::spawn_blocking(move || subscriber.delivery(&db.read().unwrap(), &change)).await.unwrap_or(Delivery::End);
+                    if delivery == Delivery::End {
+                        break;
+                    }
+                    yield StreamEvent::data(serde_json::to_string(&e).unwrap());
+                    if delivery == Delivery::SendLast {
+                        break;
+                    }
+                },
+                Ok(_) => {},
+                // the client should refetch the board, it missed something
+                Err(RecvError::Lagged(missed)) => yield StreamEvent::data(missed.to_string()).event("lagged"),
+                Err(RecvError::Closed) => break
+            }
+        }
+    })
+}
+// #endregion
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{apikey::{ApiKey, Scope}, database::Database, project::{Ownership, Project}, team::Permissions, utils};
+
+    use super::{Change, Delivery, Subscriber};
+
+    fn deliveries(db: &Database, subscriber: &Subscriber, changes: &[Change]) -> Vec<Delivery> {
+        changes.iter().map(|c| subscriber.delivery(db, c)).collect()
+    }
+
+    fn database() -> Database {
+        let mut db = Database::default();
+        db.projects.insert(1, Project { name: "p".to_string(), owner: Ownership::User(1), groups: vec![], members: HashMap::from([(2, Permissions::Viewer)]), archived: false, offered_to: None, created_at: 0, created_by: None, updated_at: 0, updated_by: None });
+        db
+    }
+
+    #[test]
+    fn losing_membership_ends_the_stream() {
+        let mut db = database();
+        let subscriber = Subscriber { user_id: 2, project_id: 1, api_key: None };
+        let edited = Change::ProjectEdited { name: "q".to_string() };
+        assert_eq!(subscriber.delivery(&db, &edited), Delivery::Send);
+
+        db.projects.get_mut(&1).unwrap().members.remove(&2);
+        assert_eq!(deliveries(&db, &subscriber, &[edited, Change::GroupDeleted { group_id: 3 }]), vec![Delivery::End, Delivery::End]);
+        // deleting the project still reaches the owner after everything else is gone
+        db.projects.remove(&1);
+        assert_eq!(Subscriber { user_id: 1, project_id: 1, api_key: None }.delivery(&db, &Change::ProjectDeleted), Delivery::SendLast);
+    }
+
+    #[test]
+    fn revoked_or_expired_keys_end_the_stream() {
+        let mut db = database();
+        let key = |expires_at: Option<u128>| ApiKey { user_id: 2, name: "k".to_string(), secret_hash: utils::hash_token("secret"), scope: Scope { read_only: true, project: Some(1) }, created_at: 0, expires_at, last_used: None };
+        db.api_keys.insert(0xa, key(None));
+        let subscriber = Subscriber { user_id: 2, project_id: 1, api_key: Some("ath_a_secret".to_string()) };
+        let edited = Change::ProjectEdited { name: "q".to_string() };
+        assert_eq!(subscriber.delivery(&db, &edited), Delivery::Send);
+
+        db.api_keys.insert(0xa, key(Some(1)));
+        assert_eq!(subscriber.delivery(&db, &edited), Delivery::End);
+        db.api_keys.remove(&0xa);
+        assert_eq!(subscriber.delivery(&db, &edited), Delivery::End);
+        // a revoked key doesn't get the last word either
+        assert_eq!(subscriber.delivery(&db, &Change::ProjectDeleted), Delivery::End);
+    }
+}
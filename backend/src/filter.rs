@@ -0,0 +1,462 @@
+use std::{collections::HashMap, path::PathBuf, sync::{Arc, RwLock}};
+
+use rocket::State;
+use serde::{Deserialize, Serialize};
+
+use crate::{access::{self, Access, Target}, config, database::Database, login_info::{LoginInformation, LoginResult}, task::{Species, Task}, team::Permissions, utils};
+
+pub const FILTER_ID_MAX: u128 = 4294967296u128; // 16^8, 2^32
+
+pub const DEFAULT_LIMIT: usize = 100;
+pub const MAX_LIMIT: usize = 500;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum FilterError {
+    KeyUnknown(String),
+    // the whole term, e.g. "due:soon"
+    ValueInvalid(String),
+    QuoteUnclosed,
+
+    NameInvalid,
+    FilterNoExist
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Assignee {
+    Me,
+    Nobody,
+    Id(u128),
+    Username(String)
+}
+
+// due dates are compared against the time the filter runs, so saved filters keep meaning "the next 7 days"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Due {
+    // due at most this many seconds from now, overdue tasks included
+    Within(u128),
+    // due later than this many seconds from now
+    Beyond(u128),
+    Overdue,
+    Unset
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    Assignee(Assignee),
+    Label(String),
+    Due(Due),
+    // true for done, events have no status and match neither
+    Status(bool),
+    Event(bool),
+    // name (part of it, any case) or id of the parent
+    Group(String),
+    Project(String),
+    // bare words, looked for in the title and description
+    Text(String)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Term {
+    pub negated: bool,
+    pub condition: Condition
+}
+
+// every term has to hold, `-` in front of a term negates it
+// e.g. `assignee:me label:bug due:<7d status:open species:task -group:"won't fix"`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Filter {
+    pub terms: Vec<Term>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Match {
+    pub project_id: u128,
+    pub group_id: u128,
+    pub task: Task
+}
+
+// splits on whitespace outside of double quotes, the quotes themselves are dropped
+fn split(query: &str) -> Result<Vec<String>, FilterError> {
+    let mut result = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in query.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    result.push(std::mem::take(&mut current));
+                }
+            },
+            c => current.push(c)
+        }
+    }
+    if quoted {
+        return Err(FilterError::QuoteUnclosed);
+    }
+    if !current.is_empty() {
+        result.push(current);
+    }
+    Ok(result)
+}
+
+// "7d", "12h", "2w" in seconds, None for anything that doesn't fit
+fn duration(value: &str) -> Option<u128> {
+    let unit = match value.chars().last()? {
+        'h' => 3600,
+        'd' => 86400,
+        'w' => 604800,
+        _ => return None
+    };
+    value[..value.len() - 1].parse::<u128>().ok()?.checked_mul(unit)
+}
+
+impl Filter {
+    pub fn parse(query: &str) -> Result<Filter, FilterError> {
+        let mut terms = vec![];
+        for raw in split(query)? {
+            let (negated, term) = match raw.strip_prefix('-') {
+                Some(t) if !t.is_empty() => (true, t),
+                _ => (false, raw.as_str())
+            };
+            let invalid = || FilterError::ValueInvalid(raw.clone());
+
+            let condition = match term.split_once(':') {
+                None => Condition::Text(term.to_lowercase()),
+                Some((_, "")) => return Err(invalid()),
+                Some((key, value)) => {
+                    let lower = value.to_lowercase();
+                    match key.to_lowercase().as_str() {
+                        "assignee" => Condition::Assignee(match lower.as_str() {
+                            "me" => Assignee::Me,
+                            "none" => Assignee::Nobody,
+                            _ => match value.parse::<u128>() {
+                                Ok(i) => Assignee::Id(i),
+                                Err(_) => Assignee::Username(value.to_string())
+                            }
+                        }),
+                        "label" => Condition::Label(lower),
+                        "due" => Condition::Due(match lower.as_str() {
+                            "overdue" => Due::Overdue,
+                            "none" => Due::Unset,
+                            _ => match (lower.strip_prefix('<'), lower.strip_prefix('>')) {
+                                (Some(d), _) => Due::Within(duration(d).ok_or_else(invalid)?),
+                                (_, Some(d)) => Due::Beyond(duration(d).ok_or_else(invalid)?),
+                                _ => return Err(invalid())
+                            }
+                        }),
+                        "status" => Condition::Status(match lower.as_str() {
+                            "open" => false,
+                            "done" => true,
+                            _ => return Err(invalid())
+                        }),
+                        "species" => Condition::Event(match lower.as_str() {
+                            "task" => false,
+                            "event" => true,
+                            _ => return Err(invalid())
+                        }),
+                        "group" => Condition::Group(lower),
+                        "project" => Condition::Project(lower),
+                        _ => return Err(FilterError::KeyUnknown(key.to_string()))
+                    }
+                }
+            };
+            terms.push(Term { negated, condition });
+        }
+        Ok(Filter { terms })
+    }
+
+    pub fn matches(&self, db: &Database, user_id: u128, now: u128, task: &Task, group_id: u128, project_id: u128) -> bool {
+        let named = |name: Option<&String>, id: u128, value: &String| {
+            value.parse::<u128>().is_ok_and(|i| i == id) || name.is_some_and(|n| n.to_lowercase().contains(value.as_str()))
+        };
+        self.terms.iter().all(|term| {
+            let holds = match &term.condition {
+                Condition::Assignee(a) => match a {
+                    Assignee::Me => task.assigned.contains(&user_id),
+                    Assignee::Nobody => task.assigned.is_empty(),
+                    Assignee::Id(i) => task.assigned.contains(i),
+                    Assignee::Username(u) => db.fetch_user_id(u).is_some_and(|i| task.assigned.contains(&i))
+                },
+                Condition::Label(l) => task.labels.contains(l),
+                Condition::Due(d) => match (d, task.due) {
+                    (Due::Unset, due) => due.is_none(),
+                    (_, None) => false,
+                    (Due::Within(s), Some(due)) => due <= now.saturating_add(*s),
+                    (Due::Beyond(s), Some(due)) => due > now.saturating_add(*s),
+                    (Due::Overdue, Some(due)) => due < now
+                },
+                Condition::Status(done) => matches!(task.species, Species::Task(s) if s == *done),
+                Condition::Event(event) => matches!(task.species, Species::Event) == *event,
+                Condition::Group(g) => named(db.groups.get(&group_id).map(|g| &g.name), group_id, g),
+                Condition::Project(p) => named(db.projects.get(&project_id).map(|p| &p.name), project_id, p),
+                Condition::Text(t) => task.title.to_lowercase().contains(t.as_str()) || task.description.to_lowercase().contains(t.as_str())
+            };
+            holds != term.negated
+        })
+    }
+
+    // matching tasks in every active project the user can see, projects by id and tasks in board order
+    pub fn run(&self, db: &Database, user_id: u128, now: u128, limit: usize) -> Vec<Match> {
+        let mut projects = db.projects.iter()
+            .filter(|(i, p)| !p.archived && access::project_permissions(db, user_id, **i).allows(Permissions::Viewer))
+            .collect::<Vec<_>>();
+        projects.sort_by_key(|(i, _)| **i);
+
+        let mut result = vec![];
+        for (project_id, project) in projects {
+            for group_id in &project.groups {
+                let group = match db.groups.get(group_id) {
+                    Some(g) if !g.archived => g,
+                    _ => continue
+                };
+                for task in group.tasks.iter().filter_map(|t| db.tasks.get(t)) {
+                    if self.matches(db, user_id, now, task, *group_id, *project_id) {
+                        result.push(Match { project_id: *project_id, group_id: *group_id, task: task.clone() });
+                        if result.len() >= limit {
+                            return result;
+                        }
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SavedFilter {
+    pub user_id: u128,
+    pub name: String,
+    pub query: String,
+    pub created_at: u128
+}
+impl SavedFilter {
+    pub fn serialize(db: &Database) -> (PathBuf, String) {
+        (config::get().data_path("saved_filters.json"), serde_json::to_string_pretty(&db.saved_filters).unwrap())
+    }
+
+    pub fn load() -> HashMap<u128, SavedFilter> {
+        utils::load_or_default(config::get().data_path("saved_filters.json"))
+    }
+
+    // saving under a name the user already has replaces that filter's query
+    pub fn save(db: &mut Database, user_id: u128, name: String, query: String) -> Result<u128, FilterError> {
+        if name.trim().is_empty() {
+            return Err(FilterError::NameInvalid);
+        }
+        Filter::parse(&query)?;
+
+        let existing = db.saved_filters.iter().find(|(_, f)| f.user_id == user_id && f.name == name).map(|(i, _)| *i);
+        let id = match existing {
+            Some(i) => {
+                db.saved_filters.get_mut(&i).unwrap().query = query;
+                i
+            },
+            None => {
+                let id = utils::generate_id(db.saved_filters.keys().copied().collect::<Vec<u128>>(), FILTER_ID_MAX);
+                db.saved_filters.insert(id, SavedFilter { user_id, name, query, created_at: utils::get_time() });
+                id
+            }
+        };
+        db.save();
+        Ok(id)
+    }
+
+    pub fn delete(db: &mut Database, user_id: u128, filter_id: u128) -> Result<(), FilterError> {
+        match db.saved_filters.get(&filter_id) {
+            Some(f) if f.user_id == user_id => {
+                db.saved_filters.remove(&filter_id);
+                db.save();
+                Ok(())
+            },
+            _ => Err(FilterError::FilterNoExist)
+        }
+    }
+
+    pub fn list(db: &Database, user_id: u128) -> Vec<(u128, SavedFilter)> {
+        let mut result = db.saved_filters.iter()
+            .filter(|(_, f)| f.user_id == user_id)
+            .map(|(i, f)| (*i, f.clone()))
+            .collect::<Vec<(u128, SavedFilter)>>();
+        result.sort_by(|(i, a), (j, b)| a.name.cmp(&b.name).then(i.cmp(j)));
+        result
+    }
+}
+
+fn run_query(db: &Database, user_id: u128, query: &str, limit: Option<usize>) -> String {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    match Filter::parse(query) {
+        Ok(f) => utils::parse_response(Ok(f.run(db, user_id, utils::get_time(), limit))),
+        Err(e) => utils::parse_response(Err(e))
+    }
+}
+
+// #region api calls
+#[post("/<query>?<limit>", data="<login>")]
+pub fn run(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, query: String, limit: Option<usize>) -> String {
+    let result = login.authorize(db, Access::Read, Target::Global);
+    match result {
+        LoginResult::Success(user_id) => run_query(&db.read().unwrap(), user_id, &utils::decode_uri(query), limit),
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/<filter_id>?<limit>", data="<login>")]
+pub fn run_saved(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, filter_id: u128, limit: Option<usize>) -> String {
+    let result = login.authorize(db, Access::Read, Target::Global);
+    match result {
+        LoginResult::Success(user_id) => {
+            let db = db.read().unwrap();
+            match db.saved_filters.get(&filter_id) {
+                Some(f) if f.user_id == user_id => run_query(&db, user_id, &f.query, limit),
+                _ => utils::parse_response(Err(FilterError::FilterNoExist))
+            }
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/<name>/<query>", data="<login>")]
+pub fn save(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, name: String, query: String) -> String {
+    let result = login.authorize(db, Access::Write, Target::Global);
+    match result {
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            match SavedFilter::save(&mut db, user_id, utils::decode_uri(name), utils::decode_uri(query)) {
+                Ok(id) => utils::parse_response(Ok(id)),
+                Err(e) => utils::parse_response(Err(e))
+            }
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/", data="<login>")]
+pub fn list(db: &State<Arc<RwLock<Database>>>, login: LoginInformation) -> String {
+    let result = login.authorize(db, Access::Read, Target::Global);
+    match result {
+        LoginResult::Success(user_id) => utils::parse_response(Ok(SavedFilter::list(&db.read().unwrap(), user_id))),
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/<filter_id>", data="<login>")]
+pub fn delete(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, filter_id: u128) -> String {
+    let result = login.authorize(db, Access::Write, Target::Global);
+    match result {
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            match SavedFilter::delete(&mut db, user_id, filter_id) {
+                Ok(_) => utils::parse_response(Ok("success")),
+                Err(e) => utils::parse_response(Err(e))
+            }
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+// #endregion
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use std::sync::{Arc, RwLock};
+
+    use rocket::State;
+
+    use crate::{apikey::{ApiKey, Scope}, config, database::Database, group::Group, indices::Index, login_info::LoginInformation, project::{Ownership, Project}, task::{Species, Task}, utils};
+
+    use super::{delete, run, save, Assignee, Condition, Due, Filter, FilterError, SavedFilter, Term};
+
+    #[test]
+    fn parses_terms() {
+        let filter = Filter::parse("assignee:me  -label:Bug due:<7d \"login page\" group:\"in review\"").unwrap();
+        assert_eq!(filter.terms, vec![
+            Term { negated: false, condition: Condition::Assignee(Assignee::Me) },
+            Term { negated: true, condition: Condition::Label("bug".to_string()) },
+            Term { negated: false, condition: Condition::Due(Due::Within(7 * 86400)) },
+            Term { negated: false, condition: Condition::Text("login page".to_string()) },
+            Term { negated: false, condition: Condition::Group("in review".to_string()) }
+        ]);
+        assert_eq!(Filter::parse("").unwrap().terms, vec![]);
+
+        assert_eq!(Filter::parse("colour:red"), Err(FilterError::KeyUnknown("colour".to_string())));
+        assert_eq!(Filter::parse("due:soon"), Err(FilterError::ValueInvalid("due:soon".to_string())));
+        assert_eq!(Filter::parse("status:"), Err(FilterError::ValueInvalid("status:".to_string())));
+        let huge = format!("due:<{}w", u128::MAX / 604800 + 1);
+        assert_eq!(Filter::parse(&huge), Err(FilterError::ValueInvalid(huge.clone())));
+        assert_eq!(Filter::parse("label:\"oops"), Err(FilterError::QuoteUnclosed));
+    }
+
+    #[test]
+    fn evaluates_against_tasks_and_parents() {
+        let mut db = Database::default();
+        db.insert_user(7, "carol".to_string());
+        db.projects.insert(1, Project { name: "Website".to_string(), owner: Ownership::User(7), groups: vec![10, 11], members: HashMap::new(), archived: false, offered_to: None, created_at: 0, created_by: None, updated_at: 0, updated_by: None });
+        db.projects.insert(2, Project { name: "Other".to_string(), owner: Ownership::User(8), groups: vec![12], members: HashMap::new(), archived: false, offered_to: None, created_at: 0, created_by: None, updated_at: 0, updated_by: None });
+        db.groups.insert(10, Group { name: "Doing".to_string(), tasks: vec![100, 101], archived: false, created_at: 0, created_by: None, updated_at: 0, updated_by: None });
+        db.groups.insert(11, Group { name: "Meetings".to_string(), tasks: vec![102], archived: false, created_at: 0, created_by: None, updated_at: 0, updated_by: None });
+        db.groups.insert(12, Group { name: "Doing".to_string(), tasks: vec![103], archived: false, created_at: 0, created_by: None, updated_at: 0, updated_by: None });
+        let task = |id: u128, title: &str, species: Species, assigned: Vec<u128>, labels: Vec<&str>, due: Option<u128>| Task {
+            id,
+            title: title.to_string(),
+            description: String::new(),
+            species,
+            assigned,
+            labels: labels.into_iter().map(|l| l.to_string()).collect(),
+            due,
+            created_at: 0,
+            created_by: None,
+            updated_at: 0,
+            updated_by: None
+        };
+        let now = 1_000_000;
+        db.tasks.insert(100, task(100, "Fix login", Species::Task(false), vec![7], vec!["bug"], Some(now + 86400)));
+        db.tasks.insert(101, task(101, "Write copy", Species::Task(true), vec![], vec![], Some(now + 30 * 86400)));
+        db.tasks.insert(102, task(102, "Kickoff", Species::Event, vec![7], vec![], Some(now - 60)));
+        db.tasks.insert(103, task(103, "Not visible", Species::Task(false), vec![7], vec!["bug"], None));
+        db.index = Index::build(&db);
+
+        let ids = |query: &str| Filter::parse(query).unwrap().run(&db, 7, now, 100).into_iter().map(|m| m.task.id).collect::<Vec<u128>>();
+
+        assert_eq!(ids(""), vec![100, 101, 102]);
+        assert_eq!(ids("assignee:me label:bug due:<7d status:open"), vec![100]);
+        assert_eq!(ids("assignee:carol -species:event"), vec![100]);
+        assert_eq!(ids("assignee:none"), vec![101]);
+        assert_eq!(ids("due:>7d"), vec![101]);
+        assert_eq!(ids("due:overdue"), vec![102]);
+        assert_eq!(ids("status:done"), vec![101]);
+        assert_eq!(ids("species:event group:meet project:web"), vec![102]);
+        assert_eq!(ids("group:10 LOGIN"), vec![100]);
+        assert_eq!(ids("-group:doing"), vec![102]);
+        // the largest window that parses still can't overflow against now
+        let widest = u128::MAX / 604800;
+        assert_eq!(ids(&format!("due:<{widest}w")), vec![100, 101, 102]);
+        assert_eq!(ids(&format!("due:>{widest}w")), Vec::<u128>::new());
+    }
+
+    #[test]
+    fn read_only_keys_run_filters_but_cannot_change_them() {
+        config::init_temp();
+        let mut db = Database::default();
+        db.insert_user(7, "carol".to_string());
+        let id = SavedFilter::save(&mut db, 7, "mine".to_string(), "status:open".to_string()).unwrap();
+        db.api_keys.insert(26, ApiKey {
+            user_id: 7,
+            name: "read only".to_string(),
+            secret_hash: utils::hash_token("secret"),
+            scope: Scope { read_only: true, project: None },
+            created_at: 0,
+            expires_at: None,
+            last_used: None
+        });
+        let db = Arc::new(RwLock::new(db));
+        let login = || LoginInformation { username: String::new(), password: String::new(), otp: None, session: None, ip: None, api_key: Some("ath_1a_secret".to_string()) };
+
+        assert!(run(State::from(&db), login(), "status%3Aopen".to_string(), None).contains("success"));
+        assert!(save(State::from(&db), login(), "other".to_string(), "status%3Adone".to_string()).contains("OutOfScope"));
+        assert!(delete(State::from(&db), login(), id).contains("OutOfScope"));
+        assert_eq!(db.read().unwrap().saved_filters.len(), 1);
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{backup::Snapshot, database::Database};
+
+const MAX_REPAIR_PASSES: usize = 8;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FsckMode {
+    Off,
+    Check,
+    Repair
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum Issue {
+    // ids listed by a parent that point at nothing
+    DanglingGroup { project_id: u128, group_id: u128 },
+    DanglingTask { group_id: u128, task_id: u128 },
+
+    // listed more than once, either by several parents or twice by the same one
+    DuplicateGroup { group_id: u128, projects: Vec<u128> },
+    DuplicateTask { task_id: u128, groups: Vec<u128> },
+
+    // exist but no parent lists them, so the cascading deletes never reach them
+    OrphanGroup(u128),
+    OrphanTask(u128),
+
+    UnknownAssignee { task_id: u128, user_id: u128 },
+    MismatchedTaskId { task_id: u128, stored_id: u128 }
+}
+impl Issue {
+    pub fn describe(&self) -> String {
+        match self {
+            Issue::DanglingGroup { project_id, group_id } => format!("project {project_id} lists missing group {group_id}"),
+            Issue::DanglingTask { group_id, task_id } => format!("group {group_id} lists missing task {task_id}"),
+            Issue::DuplicateGroup { group_id, projects } => format!("group {group_id} is listed by projects {projects:?}"),
+            Issue::DuplicateTask { task_id, groups } => format!("task {task_id} is listed by groups {groups:?}"),
+            Issue::OrphanGroup(group_id) => format!("group {group_id} has no project"),
+            Issue::OrphanTask(task_id) => format!("task {task_id} has no group"),
+            Issue::UnknownAssignee { task_id, user_id } => format!("task {task_id} is assigned to unknown user {user_id}"),
+            Issue::MismatchedTaskId { task_id, stored_id } => format!("task {task_id} has id {stored_id} stored")
+        }
+    }
+}
+
+pub fn check(db: &Database) -> Vec<Issue> {
+    let mut result = vec![];
+
+    // parents are visited in id order so reports (and which parent keeps a duplicate) are stable between runs
+    let mut project_ids = db.projects.keys().copied().collect::<Vec<u128>>();
+    project_ids.sort();
+    let mut group_ids = db.groups.keys().copied().collect::<Vec<u128>>();
+    group_ids.sort();
+    let mut task_ids = db.tasks.keys().copied().collect::<Vec<u128>>();
+    task_ids.sort();
+
+    let mut group_parents: HashMap<u128, Vec<u128>> = HashMap::new();
+    for project_id in &project_ids {
+        for group_id in &db.projects[project_id].groups {
+            if db.groups.contains_key(group_id) {
+                group_parents.entry(*group_id).or_default().push(*project_id);
+            } else {
+                result.push(Issue::DanglingGroup { project_id: *project_id, group_id: *group_id });
+            }
+        }
+    }
+
+    let mut task_parents: HashMap<u128, Vec<u128>> = HashMap::new();
+    for group_id in &group_ids {
+        for task_id in &db.groups[group_id].tasks {
+            if db.tasks.contains_key(task_id) {
+                task_parents.entry(*task_id).or_default().push(*group_id);
+            } else {
+                result.push(Issue::DanglingTask { group_id: *group_id, task_id: *task_id });
+            }
+        }
+    }
+
+    for group_id in &group_ids {
+        match group_parents.get(group_id) {
+            Some(projects) if projects.len() > 1 => result.push(Issue::DuplicateGroup { group_id: *group_id, projects: projects.clone() }),
+            Some(_) => {},
+            None => result.push(Issue::OrphanGroup(*group_id))
+        }
+    }
+
+    for task_id in &task_ids {
+        match task_parents.get(task_id) {
+            Some(groups) if groups.len() > 1 => result.push(Issue::DuplicateTask { task_id: *task_id, groups: groups.clone() }),
+            Some(_) => {},
+            None => result.push(Issue::OrphanTask(*task_id))
+        }
+
+        let task = &db.tasks[task_id];
+        if task.id != *task_id {
+            result.push(Issue::MismatchedTaskId { task_id: *task_id, stored_id: task.id });
+        }
+        for user_id in &task.assigned {
+            if !db.users.contains_key(user_id) {
+                result.push(Issue::UnknownAssignee { task_id: *task_id, user_id: *user_id });
+            }
+        }
+    }
+
+    result
+}
+
+// fixes everything check() reports and returns what was found along the way
+// removing an orphan group can orphan its tasks, so this keeps going until a pass comes back clean
+// a snapshot is taken first since orphans are deleted rather than reattached
+pub fn repair(db: &mut Database) -> Vec<Issue> {
+    let mut found = vec![];
+
+    let mut issues = check(db);
+    if issues.is_empty() {
+        return found;
+    }
+    Snapshot::take(db);
+
+    for _ in 0..MAX_REPAIR_PASSES {
+        if issues.is_empty() {
+            break;
+        }
+        for issue in &issues {
+            fix(db, issue);
+        }
+        found.append(&mut issues);
+        issues = check(db);
+    }
+
+    db.reindex();
+    db.save();
+    found
+}
+
+fn fix(db: &mut Database, issue: &Issue) {
+    match issue {
+        Issue::DanglingGroup { project_id, group_id } => {
+            if let Some(p) = db.projects.get_mut(project_id) {
+                p.groups.retain(|g| g != group_id);
+            }
+        },
+        Issue::DanglingTask { group_id, task_id } => {
+            if let Some(g) = db.groups.get_mut(group_id) {
+                g.tasks.retain(|t| t != task_id);
+            }
+        },
+        Issue::DuplicateGroup { group_id, projects } => {
+            // the first listing wins, every later one is dropped
+            let mut seen = false;
+            for project_id in projects.iter().collect::<HashSet<&u128>>() {
+                if let Some(p) = db.projects.get_mut(project_id) {
+                    p.groups.retain(|g| {
+                        if g != group_id {
+                            return true;
+                        }
+                        let keep = !seen && projects[0] == *project_id;
+                        seen |= keep;
+                        keep
+                    });
+                }
+            }
+        },
+        Issue::DuplicateTask { task_id, groups } => {
+            let mut seen = false;
+            for group_id in groups.iter().collect::<HashSet<&u128>>() {
+                if let Some(g) = db.groups.get_mut(group_id) {
+                    g.tasks.retain(|t| {
+                        if t != task_id {
+                            return true;
+                        }
+                        let keep = !seen && groups[0] == *group_id;
+                        seen |= keep;
+                        keep
+                    });
+                }
+            }
+        },
+        Issue::OrphanGroup(group_id) => {
+            db.groups.remove(group_id);
+        },
+        Issue::OrphanTask(task_id) => {
+            db.tasks.remove(task_id);
+        },
+        Issue::UnknownAssignee { task_id, user_id } => {
+            if let Some(t) = db.tasks.get_mut(task_id) {
+                t.assigned.retain(|u| u != user_id);
+            }
+        },
+        Issue::MismatchedTaskId { task_id, .. } => {
+            if let Some(t) = db.tasks.get_mut(task_id) {
+                t.id = *task_id;
+            }
+        }
+    }
+}
+
+pub fn print_report(issues: &[Issue], repaired: bool) {
+    if issues.is_empty() {
+        println!("fsck: no issues found");
+        return;
+    }
+    for i in issues {
+        println!("fsck: {}", i.describe());
+    }
+    if repaired {
+        println!("fsck: repaired {} issue(s)", issues.len());
+    } else {
+        println!("fsck: found {} issue(s), run `backend fsck --repair` to fix them", issues.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{config, database::Database, group::Group, project::{Ownership, Project}, task::{Species, Task}};
+
+    use super::{check, repair, Issue};
+
+    fn broken() -> Database {
+        let mut db = Database::default();
+        let project = |groups: Vec<u128>| Project { name: "p".to_string(), owner: Ownership::User(1), groups, members: HashMap::new(), archived: false, offered_to: None, created_at: 0, created_by: None, updated_at: 0, updated_by: None };
+        let group = |tasks: Vec<u128>| Group { name: "g".to_string(), tasks, archived: false, created_at: 0, created_by: None, updated_at: 0, updated_by: None };
+        let task = |id: u128| Task { id, title: "t".to_string(), description: String::new(), species: Species::Task(false), assigned: vec![], labels: vec![], due: None, created_at: 0, created_by: None, updated_at: 0, updated_by: None };
+
+        // group 11 and task 101 are listed but missing, task 100 is listed twice
+        db.projects.insert(1, project(vec![10, 11, 12]));
+        db.groups.insert(10, group(vec![100, 101]));
+        db.groups.insert(12, group(vec![100]));
+        // group 20 has no project, so its task 200 only becomes an orphan once the group is gone
+        db.groups.insert(20, group(vec![200]));
+        for t in [100, 200, 300] {
+            db.tasks.insert(t, task(t));
+        }
+        db.reindex();
+        db
+    }
+
+    #[test]
+    fn reports_dangling_and_orphaned_records() {
+        assert_eq!(check(&broken()), vec![
+            Issue::DanglingGroup { project_id: 1, group_id: 11 },
+            Issue::DanglingTask { group_id: 10, task_id: 101 },
+            Issue::OrphanGroup(20),
+            Issue::DuplicateTask { task_id: 100, groups: vec![10, 12] },
+            Issue::OrphanTask(300)
+        ]);
+    }
+
+    #[test]
+    fn repair_removes_orphans_and_keeps_listed_records() {
+        config::init_temp();
+        let mut db = broken();
+
+        let found = repair(&mut db);
+        // the second pass picks up the task left behind by the removed group
+        assert!(found.contains(&Issue::OrphanTask(200)));
+        assert!(check(&db).is_empty());
+
+        assert_eq!(db.projects[&1].groups, vec![10, 12]);
+        assert_eq!(db.groups[&10].tasks, vec![100]);
+        assert!(db.groups[&12].tasks.is_empty());
+        let mut groups = db.groups.keys().copied().collect::<Vec<u128>>();
+        groups.sort();
+        assert_eq!(groups, vec![10, 12]);
+        assert_eq!(db.tasks.keys().copied().collect::<Vec<u128>>(), vec![100]);
+    }
+}
@@ -1,90 +1,138 @@
-use std::{collections::HashMap, fs, sync::Mutex};
+use std::{collections::HashMap, fs, path::PathBuf, sync::{Arc, RwLock}};
 
-use rand::Rng;
 use rocket::State;
 use serde::{Deserialize, Serialize};
 
-use crate::{database::Database, login_info::{LoginInformation, LoginResult}, project::Project, task::{self, Task}, utils};
+use crate::{access::{Access, Target}, activity::Activity, config, database::Database, events::{self, Change}, login_info::{LoginInformation, LoginResult}, project::Project, search::SearchIndex, task::Task, trash::Trash, utils};
 
 pub const GROUP_ID_MAX: u128 = 4294967296u128; // 16^8, 2^32s
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Group {
     pub name: String,
 
-    pub tasks: Vec<u128>
+    pub tasks: Vec<u128>,
+
+    // left out of the project's groups unless asked for
+    #[serde(default)]
+    pub archived: bool,
+
+    // kept up to date by the domain functions, see touch
+    #[serde(default)]
+    pub created_at: u128,
+    // None for anything from before authors were tracked that the activity log knows nothing about
+    #[serde(default)]
+    pub created_by: Option<u128>,
+    #[serde(default)]
+    pub updated_at: u128,
+    #[serde(default)]
+    pub updated_by: Option<u128>
 }
 impl Group {
-    pub fn save(db: &Database) {
-        fs::write("data/groups.json", serde_json::to_string_pretty(&db.groups).unwrap()).unwrap();
+    pub fn serialize(db: &Database) -> (PathBuf, String) {
+        (config::get().data_path("groups.json"), serde_json::to_string_pretty(&db.groups).unwrap())
     }
 
     pub fn load() -> HashMap<u128, Group> {
-        serde_json::from_str(fs::read_to_string("data/groups.json").unwrap().as_str()).unwrap()
+        serde_json::from_str(fs::read_to_string(config::get().data_path("groups.json")).unwrap().as_str()).unwrap()
     }
 
     pub fn parent_of_task(db: &Database, task_id: u128) -> Option<u128> {
-        for (i, g) in &db.groups {
-            if g.tasks.contains(&task_id) {
-                return Some(*i);
-            }
+        db.index.task_group.get(&task_id).copied()
+    }
+
+    // records who changed the group last and when, called by everything that edits it
+    pub fn touch(&mut self, actor: u128) {
+        self.updated_at = utils::get_time();
+        self.updated_by = Some(actor);
+    }
+
+    pub fn create(db: &mut Database, actor: u128, project_id: &u128, name: String) {
+        if let Some(p) = db.projects.get_mut(project_id) {
+            let id = utils::generate_id(db.groups.keys().copied().collect::<Vec<u128>>(), GROUP_ID_MAX);
+            p.groups.push(id);
+            db.index.group_project.insert(id, *project_id);
+            let now = utils::get_time();
+            let group = Group {
+                name: name.clone(),
+                tasks: vec![],
+                archived: false,
+                created_at: now,
+                created_by: Some(actor),
+                updated_at: now,
+                updated_by: Some(actor)
+            };
+            Activity::record(db, actor, Some(*project_id), Target::Group(id), "created", None::<()>, Some(&group));
+            db.groups.insert(id, group);
+            SearchIndex::refresh(db, Target::Group(id));
+            db.save();
+            events::emit(db, Some(*project_id), Change::GroupCreated { group_id: id, name });
         }
-        None
     }
 
-    pub fn create(db: &mut Database, project_id: &u128, name: String) {
-        match db.projects.get_mut(&project_id) {
-            Some(p) => {
-                let id = utils::generate_id(db.groups.keys().map(|i| *i).collect::<Vec<u128>>(), GROUP_ID_MAX);
-                p.groups.push(id);
-                db.groups.insert(id, Group {
-                    name,
-                    tasks: vec![]
-                });
-                db.save();
-            },
-            None => {}
+    pub fn delete(db: &mut Database, actor: u128, group_id: u128) {
+        if db.groups.contains_key(&group_id) {
+            Trash::put(db, actor, Target::Group(group_id));
+            Group::remove(db, actor, group_id);
         }
     }
 
-    pub fn delete(db: &mut Database, group_id: u128) {
+    pub fn remove(db: &mut Database, actor: u128, group_id: u128) {
         if db.groups.contains_key(&group_id) {
-            match Project::parent_of_group(db, group_id).map_or(None, |i| db.projects.get_mut(&i)) {
-                Some(p) => {
-                    let indices = p.groups.iter().enumerate().map(|(i, e)| (i, *e)).filter(|(_, g)| *g == group_id).collect::<Vec<(usize, u128)>>();
-                    if !indices.is_empty() {
-                        p.groups.remove(indices[0].0);
-                        for t in db.groups.get(&group_id).unwrap().tasks.clone() {
-                            Task::delete(db, t);
-                        }
-                        db.groups.remove(&group_id);
-                        db.save();
+            if let Some(p) = Project::parent_of_group(db, group_id).and_then(|i| db.projects.get_mut(&i)) {
+                let indices = p.groups.iter().enumerate().map(|(i, e)| (i, *e)).filter(|(_, g)| *g == group_id).collect::<Vec<(usize, u128)>>();
+                if !indices.is_empty() {
+                    p.groups.remove(indices[0].0);
+                    for t in db.groups.get(&group_id).unwrap().tasks.clone() {
+                        Task::remove(db, actor, t);
                     }
-                },
-                None => {}
+                    let project_id = Project::parent_of_group(db, group_id);
+                    let group = db.groups.remove(&group_id);
+                    db.index.group_project.remove(&group_id);
+                    Activity::record(db, actor, project_id, Target::Group(group_id), "deleted", group, None::<()>);
+                    SearchIndex::refresh(db, Target::Group(group_id));
+                    db.save();
+                    events::emit(db, project_id, Change::GroupDeleted { group_id });
+                }
             }
         }
     }
 
-    pub fn edit(db: &mut Database, group_id: u128, name: String) {
+    pub fn set_archived(db: &mut Database, actor: u128, group_id: u128, archived: bool) {
         match db.groups.get_mut(&group_id) {
-            Some(g) => {
-                g.name = name;
-                db.save()
+            Some(g) if g.archived != archived => {
+                g.archived = archived;
+                g.touch(actor);
+                let project_id = Project::parent_of_group(db, group_id);
+                Activity::record(db, actor, project_id, Target::Group(group_id), if archived { "archived" } else { "unarchived" }, Some(!archived), Some(archived));
+                db.save();
+                events::emit(db, project_id, Change::GroupArchived { group_id, archived });
             },
-            None => {}
+            _ => {}
+        }
+    }
+
+    pub fn edit(db: &mut Database, actor: u128, group_id: u128, name: String) {
+        if let Some(g) = db.groups.get_mut(&group_id) {
+            let before = std::mem::replace(&mut g.name, name.clone());
+            g.touch(actor);
+            let project_id = Project::parent_of_group(db, group_id);
+            Activity::record(db, actor, project_id, Target::Group(group_id), "edited", Some(before), Some(&name));
+            SearchIndex::refresh(db, Target::Group(group_id));
+            db.save();
+            events::emit(db, project_id, Change::GroupEdited { group_id, name });
         }
     }
 }
 
 // #region api calls
 #[post("/<project_id>/<name>", data="<login>")]
-pub fn create(db: &State<Mutex<Database>>, login: LoginInformation, project_id: u128, name: String) -> String {
-    let mut db = db.lock().unwrap();
-    let result = login.login(&mut db);
+pub fn create(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, project_id: u128, name: String) -> String {
+    let result = login.authorize(db, Access::Write, Target::Project(project_id));
     match result {
-        LoginResult::Success(_) => {
-            Group::create(&mut db, &project_id, name);
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            Group::create(&mut db, user_id, &project_id, name);
             utils::parse_response(Ok("success"))
         },
         _ => utils::parse_response(Err(result))
@@ -92,12 +140,12 @@ pub fn create(db: &State<Mutex<Database>>, login: LoginInformation, project_id:
 }
 
 #[post("/<group_id>", data="<login>")]
-pub fn delete(db: &State<Mutex<Database>>, login: LoginInformation, group_id: u128) -> String {
-    let mut db = db.lock().unwrap();
-    let result = login.login(&mut db);
+pub fn delete(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, group_id: u128) -> String {
+    let result = login.authorize(db, Access::Write, Target::Group(group_id));
     match result {
-        LoginResult::Success(_) => {
-            Group::delete(&mut db, group_id);
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            Group::delete(&mut db, user_id, group_id);
             utils::parse_response(Ok("success"))
         },
         _ => utils::parse_response(Err(result))
@@ -105,16 +153,41 @@ pub fn delete(db: &State<Mutex<Database>>, login: LoginInformation, group_id: u1
 }
 
 #[post("/<group_id>/<name>", data="<login>")]
-pub fn edit(db: &State<Mutex<Database>>, login: LoginInformation, group_id: u128, name: String) -> String {
-    let mut db = db.lock().unwrap();
-    let result = login.login(&mut db);
+pub fn edit(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, group_id: u128, name: String) -> String {
+    let result = login.authorize(db, Access::Write, Target::Group(group_id));
     match result {
-        LoginResult::Success(_) => {
-            Group::edit(&mut db, group_id, name);
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            Group::edit(&mut db, user_id, group_id, name);
             utils::parse_response(Ok("success"))
         },
         _ => utils::parse_response(Err(result))
     }    
 }
-// #endregion
 
+#[post("/<group_id>", data="<login>")]
+pub fn archive(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, group_id: u128) -> String {
+    let result = login.authorize(db, Access::Write, Target::Group(group_id));
+    match result {
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            Group::set_archived(&mut db, user_id, group_id, true);
+            utils::parse_response(Ok("success"))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/<group_id>", data="<login>")]
+pub fn unarchive(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, group_id: u128) -> String {
+    let result = login.authorize(db, Access::Write, Target::Group(group_id));
+    match result {
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            Group::set_archived(&mut db, user_id, group_id, false);
+            utils::parse_response(Ok("success"))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+// #endregion
@@ -0,0 +1,66 @@
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config, ldap::LdapProvider, soterius::CredentialFile};
+
+static PROVIDER: OnceLock<Box<dyn IdentityProvider>> = OnceLock::new();
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    Soterius, // the shared soterius users file
+    Local, // athena's own credentials file in the data dir
+    Ldap
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Authentication {
+    // None when the provider has no ids of its own and athena should assign one
+    Success(Option<u128>),
+    UnknownUser,
+    WrongPassword,
+    Unavailable
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Registration {
+    Success(u128),
+    UsernameTaken,
+    Unsupported,
+    Unavailable
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordUpdate {
+    Success,
+    UnknownUser,
+    Unsupported,
+    Unavailable
+}
+
+// where usernames and passwords are checked, athena's own User records are kept in sync by the caller
+pub trait IdentityProvider: Send + Sync {
+    fn authenticate(&self, username: &str, password: &str) -> Authentication;
+
+    fn register(&self, _username: &str, _password: &str) -> Registration {
+        Registration::Unsupported
+    }
+
+    fn set_password(&self, _username: &str, _password: &str) -> PasswordUpdate {
+        PasswordUpdate::Unsupported
+    }
+}
+
+pub fn build(kind: ProviderKind) -> Box<dyn IdentityProvider> {
+    let config = config::get();
+    match kind {
+        ProviderKind::Soterius => Box::new(CredentialFile::new(config.soterius_path.clone(), true)),
+        ProviderKind::Local => Box::new(CredentialFile::new(config.data_path("credentials.json"), false)),
+        ProviderKind::Ldap => Box::new(LdapProvider::new(config.ldap_address.clone(), config.ldap_user_dn.clone(), config.ldap_plaintext))
+    }
+}
+
+pub fn get() -> &'static dyn IdentityProvider {
+    PROVIDER.get_or_init(|| build(config::get().identity_provider)).as_ref()
+}
@@ -0,0 +1,127 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::database::Database;
+
+// reverse lookups that would otherwise be linear scans over the whole database
+// never persisted, built on load and kept up to date by the domain functions that mutate the maps
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct Index {
+    pub task_group: HashMap<u128, u128>,
+    pub group_project: HashMap<u128, u128>,
+    pub username_id: HashMap<String, u128>,
+    pub user_teams: HashMap<u128, HashSet<u128>>
+}
+impl Index {
+    pub fn build(db: &Database) -> Index {
+        let mut result = Index::default();
+
+        // sorted so an id listed by two parents (see fsck) always resolves to the same one
+        let mut projects = db.projects.iter().collect::<Vec<_>>();
+        projects.sort_by_key(|(i, _)| **i);
+        for (project_id, p) in projects {
+            for group_id in &p.groups {
+                result.group_project.entry(*group_id).or_insert(*project_id);
+            }
+        }
+
+        let mut groups = db.groups.iter().collect::<Vec<_>>();
+        groups.sort_by_key(|(i, _)| **i);
+        for (group_id, g) in groups {
+            for task_id in &g.tasks {
+                result.task_group.entry(*task_id).or_insert(*group_id);
+            }
+        }
+
+        for (user_id, u) in &db.users {
+            result.username_id.insert(u.username.clone(), *user_id);
+        }
+
+        for (team_id, t) in &db.teams {
+            for user_id in t.members.keys() {
+                result.user_teams.entry(*user_id).or_default().insert(*team_id);
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use rand::{rngs::StdRng, seq::IteratorRandom, Rng, SeedableRng};
+
+    use crate::{config, database::Database, group::Group, project::{Ownership, Project}, search::SearchIndex, task::{Species, Task}, team::{Permissions, Team}};
+
+    use super::Index;
+
+    fn scan_task_group(db: &Database, task_id: u128) -> Option<u128> {
+        let mut parents = db.groups.iter().filter(|(_, g)| g.tasks.contains(&task_id)).map(|(i, _)| *i).collect::<Vec<u128>>();
+        parents.sort();
+        parents.first().copied()
+    }
+
+    fn scan_group_project(db: &Database, group_id: u128) -> Option<u128> {
+        let mut parents = db.projects.iter().filter(|(_, p)| p.groups.contains(&group_id)).map(|(i, _)| *i).collect::<Vec<u128>>();
+        parents.sort();
+        parents.first().copied()
+    }
+
+    fn assert_consistent(db: &Database) {
+        assert_eq!(db.index, Index::build(db));
+        assert_eq!(db.search, SearchIndex::build(db));
+
+        for task_id in db.tasks.keys() {
+            assert_eq!(Group::parent_of_task(db, *task_id), scan_task_group(db, *task_id));
+        }
+        for group_id in db.groups.keys() {
+            assert_eq!(Project::parent_of_group(db, *group_id), scan_group_project(db, *group_id));
+        }
+        for (user_id, u) in &db.users {
+            assert_eq!(db.fetch_user_id(&u.username), Some(*user_id));
+        }
+        assert!(db.index.task_group.keys().all(|t| db.tasks.contains_key(t)));
+        assert!(db.index.group_project.keys().all(|g| db.groups.contains_key(g)));
+    }
+
+    #[test]
+    fn index_matches_full_scan_after_random_operations() {
+        config::init_temp();
+
+        let mut db = Database::default();
+        let mut rng = StdRng::seed_from_u64(29);
+
+        for i in 0..8u128 {
+            db.insert_user(i, format!("user{i}"));
+        }
+        db.teams.insert(1, Team { name: "team".to_string(), members: HashMap::from([(0, Permissions::Admin), (3, Permissions::Viewer)]) });
+        db.index = Index::build(&db);
+
+        for _ in 0..400 {
+            match rng.gen_range(0..6) {
+                0 => Project::create(&mut db, 0, Ownership::User(rng.gen_range(0..8)), "project".to_string()),
+                1 => if let Some(p) = db.projects.keys().choose(&mut rng).copied() {
+                    Group::create(&mut db, 0, &p, "group".to_string());
+                },
+                2 => if let Some(g) = db.groups.keys().choose(&mut rng).copied() {
+                    Task::create(&mut db, 0, g, "task".to_string(), String::new(), Species::Task(false));
+                },
+                3 => if let Some(t) = db.tasks.keys().choose(&mut rng).copied() {
+                    Task::delete(&mut db, 0, t);
+                },
+                4 => if let Some(g) = db.groups.keys().choose(&mut rng).copied() {
+                    Group::delete(&mut db, 0, g);
+                },
+                _ => if rng.gen_bool(0.3) {
+                    if let Some(p) = db.projects.keys().choose(&mut rng).copied() {
+                        Project::delete(&mut db, 0, p);
+                    }
+                }
+            }
+            assert_consistent(&db);
+        }
+
+        assert_eq!(db.index.user_teams.get(&3).map(|t| t.contains(&1)), Some(true));
+    }
+}
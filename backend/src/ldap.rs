@@ -0,0 +1,321 @@
+use std::{io::{Read, Write}, net::{TcpStream, ToSocketAddrs}, sync::Arc, time::Duration};
+
+use rocket::tokio::task;
+use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::identity::{Authentication, IdentityProvider};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_RESPONSE: usize = 16384;
+
+const LDAP_PORT: u16 = 389;
+const LDAPS_PORT: u16 = 636;
+
+const BIND_MESSAGE_ID: u8 = 1;
+const UNBIND_MESSAGE_ID: u8 = 2;
+
+const RESULT_SUCCESS: u8 = 0;
+const RESULT_INVALID_CREDENTIALS: u8 = 49;
+
+// checks passwords with an LDAP v3 simple bind as the user, nothing else is asked of the directory
+// the directory has no athena ids, so users get one assigned on their first login
+pub struct LdapProvider {
+    // host:port, without the scheme
+    address: String,
+    host: String,
+    // set for ldaps:// addresses, the certificate is checked against the webpki roots
+    tls: Option<Arc<ClientConfig>>,
+    // a simple bind sends the password as is, so plain ldap:// is refused unless this is on
+    plaintext: bool,
+    // e.g. "uid={username},ou=people,dc=example,dc=org"
+    user_dn: String
+}
+impl LdapProvider {
+    // "ldaps://host[:port]", or "ldap://host[:port]" and a bare "host:port" for plain connections
+    pub fn new(address: String, user_dn: String, plaintext: bool) -> LdapProvider {
+        let (rest, tls, port) = match (address.strip_prefix("ldaps://"), address.strip_prefix("ldap://")) {
+            (Some(rest), _) => (rest, true, LDAPS_PORT),
+            (_, Some(rest)) => (rest, false, LDAP_PORT),
+            _ => (address.as_str(), false, LDAP_PORT)
+        };
+        let rest = rest.trim_end_matches('/');
+        let (host, address) = match rest.rsplit_once(':') {
+            Some((host, p)) if p.parse::<u16>().is_ok() && (!host.contains(':') || host.ends_with(']')) => (host, rest.to_string()),
+            _ => (rest, format!("{rest}:{port}"))
+        };
+        let tls = tls.then(|| {
+            let roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+            Arc::new(ClientConfig::builder().with_root_certificates(roots).with_no_client_auth())
+        });
+        LdapProvider {
+            address,
+            host: host.trim_start_matches('[').trim_end_matches(']').to_string(),
+            tls,
+            plaintext,
+            user_dn
+        }
+    }
+
+    pub fn dn(&self, username: &str) -> String {
+        self.user_dn.replace("{username}", &escape_dn_value(username))
+    }
+
+    // the first address the host resolves to that answers within the timeout
+    fn connect(&self) -> Option<TcpStream> {
+        let stream = self.address.to_socket_addrs().ok()?.find_map(|a| TcpStream::connect_timeout(&a, TIMEOUT).ok())?;
+        stream.set_read_timeout(Some(TIMEOUT)).ok()?;
+        stream.set_write_timeout(Some(TIMEOUT)).ok()?;
+        Some(stream)
+    }
+
+    fn bind(&self, dn: &str, password: &str) -> Option<u8> {
+        let mut stream = self.connect()?;
+        match &self.tls {
+            Some(config) => {
+                let name = ServerName::try_from(self.host.clone()).ok()?;
+                let connection = ClientConnection::new(config.clone(), name).ok()?;
+                exchange(&mut StreamOwned::new(connection, stream), dn, password)
+            },
+            None => exchange(&mut stream, dn, password)
+        }
+    }
+}
+impl IdentityProvider for LdapProvider {
+    fn authenticate(&self, username: &str, password: &str) -> Authentication {
+        // an empty password is an "unauthenticated bind", which most servers accept for any dn
+        if password.is_empty() || username.is_empty() {
+            return Authentication::WrongPassword;
+        }
+        if self.tls.is_none() && !self.plaintext {
+            println!("refusing to send a password to {} without tls, use ldaps:// or turn on ldap_plaintext", self.address);
+            return Authentication::Unavailable;
+        }
+
+        // logins are checked from the route handlers, a slow directory shouldn't hold up one of rocket's async workers
+        match task::block_in_place(|| self.bind(&self.dn(username), password)) {
+            Some(RESULT_SUCCESS) => Authentication::Success(None),
+            // the directory doesn't say whether the dn exists, so this covers unknown users as well
+            Some(RESULT_INVALID_CREDENTIALS) => Authentication::WrongPassword,
+            _ => Authentication::Unavailable
+        }
+    }
+}
+
+fn exchange(stream: &mut (impl Read + Write), dn: &str, password: &str) -> Option<u8> {
+    stream.write_all(&bind_request(BIND_MESSAGE_ID, dn, password)).ok()?;
+
+    let mut response = vec![];
+    let mut buffer = [0u8; 1024];
+    let code = loop {
+        let read = stream.read(&mut buffer).ok()?;
+        if read == 0 || response.len() + read > MAX_RESPONSE {
+            return None;
+        }
+        response.extend_from_slice(&buffer[..read]);
+        if let Some(code) = parse_bind_response(&response) {
+            break code;
+        }
+    };
+
+    let _ = stream.write_all(&unbind_request(UNBIND_MESSAGE_ID));
+    let _ = stream.flush();
+    Some(code)
+}
+
+// RFC 4514 escaping for an attribute value
+pub fn escape_dn_value(value: &str) -> String {
+    let mut result = String::new();
+    for (i, c) in value.chars().enumerate() {
+        match c {
+            ',' | '+' | '"' | '\\' | '<' | '>' | ';' | '=' => {
+                result.push('\\');
+                result.push(c);
+            },
+            '#' if i == 0 => result.push_str("\\#"),
+            ' ' if i == 0 || i == value.chars().count() - 1 => result.push_str("\\ "),
+            '\0' => result.push_str("\\00"),
+            _ => result.push(c)
+        }
+    }
+    result
+}
+
+// #region ber
+fn encode_length(length: usize) -> Vec<u8> {
+    if length < 0x80 {
+        return vec![length as u8];
+    }
+    let bytes = length.to_be_bytes().iter().copied().skip_while(|b| *b == 0).collect::<Vec<u8>>();
+    let mut result = vec![0x80 | bytes.len() as u8];
+    result.extend(bytes);
+    result
+}
+
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut result = vec![tag];
+    result.extend(encode_length(content.len()));
+    result.extend_from_slice(content);
+    result
+}
+
+// (tag, content, rest), None if the buffer doesn't hold the whole element yet
+fn read_tlv(buffer: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *buffer.first()?;
+    let first = *buffer.get(1)? as usize;
+    let (length, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let count = first & 0x7f;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let bytes = buffer.get(2..2 + count)?;
+        (bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize), 2 + count)
+    };
+    let content = buffer.get(header..header + length)?;
+    Some((tag, content, &buffer[header + length..]))
+}
+
+pub fn bind_request(message_id: u8, dn: &str, password: &str) -> Vec<u8> {
+    let mut bind = tlv(0x02, &[3]); // version
+    bind.extend(tlv(0x04, dn.as_bytes())); // name
+    bind.extend(tlv(0x80, password.as_bytes())); // simple authentication
+
+    let mut message = tlv(0x02, &[message_id]);
+    message.extend(tlv(0x60, &bind)); // [APPLICATION 0] BindRequest
+    tlv(0x30, &message)
+}
+
+pub fn unbind_request(message_id: u8) -> Vec<u8> {
+    let mut message = tlv(0x02, &[message_id]);
+    message.extend(tlv(0x42, &[])); // [APPLICATION 2] UnbindRequest
+    tlv(0x30, &message)
+}
+
+pub fn parse_bind_response(buffer: &[u8]) -> Option<u8> {
+    let (tag, message, _) = read_tlv(buffer)?;
+    if tag != 0x30 {
+        return None;
+    }
+    let (_, _, rest) = read_tlv(message)?; // message id
+    let (tag, response, _) = read_tlv(rest)?;
+    if tag != 0x61 { // [APPLICATION 1] BindResponse
+        return None;
+    }
+    let (tag, code, _) = read_tlv(response)?;
+    if tag != 0x0a || code.len() != 1 {
+        return None;
+    }
+    Some(code[0])
+}
+
+// (dn, password) out of a bind request, only needed by the stand-in server in the tests
+#[cfg(test)]
+pub fn parse_bind_request(buffer: &[u8]) -> Option<(String, String)> {
+    let (_, message, _) = read_tlv(buffer)?;
+    let (_, _, rest) = read_tlv(message)?;
+    let (tag, bind, _) = read_tlv(rest)?;
+    if tag != 0x60 {
+        return None;
+    }
+    let (_, _, rest) = read_tlv(bind)?; // version
+    let (_, dn, rest) = read_tlv(rest)?;
+    let (_, password, _) = read_tlv(rest)?;
+    Some((String::from_utf8(dn.to_vec()).ok()?, String::from_utf8(password.to_vec()).ok()?))
+}
+
+#[cfg(test)]
+pub fn bind_response(message_id: u8, code: u8) -> Vec<u8> {
+    let mut response = tlv(0x0a, &[code]);
+    response.extend(tlv(0x04, &[])); // matched dn
+    response.extend(tlv(0x04, &[])); // diagnostic message
+
+    let mut message = tlv(0x02, &[message_id]);
+    message.extend(tlv(0x61, &response));
+    tlv(0x30, &message)
+}
+// #endregion
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{Read, Write}, net::TcpListener, thread};
+
+    use crate::identity::{Authentication, IdentityProvider, PasswordUpdate};
+
+    use super::*;
+
+    const DN: &str = "uid={username},ou=people,dc=test";
+
+    // accepts `connections` binds, succeeding only for alice/secret
+    fn stand_in_server(connections: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming().take(connections) {
+                let mut stream = stream.unwrap();
+                let mut buffer = [0u8; 1024];
+                let read = stream.read(&mut buffer).unwrap();
+                let code = match parse_bind_request(&buffer[..read]) {
+                    Some((dn, password)) if dn == "uid=alice,ou=people,dc=test" && password == "secret" => RESULT_SUCCESS,
+                    _ => RESULT_INVALID_CREDENTIALS
+                };
+                stream.write_all(&bind_response(BIND_MESSAGE_ID, code)).unwrap();
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn binds_against_stand_in_server() {
+        let provider = LdapProvider::new(stand_in_server(2), DN.to_string(), true);
+        assert_eq!(provider.authenticate("alice", "secret"), Authentication::Success(None));
+        assert_eq!(provider.authenticate("alice", "wrong"), Authentication::WrongPassword);
+        assert_eq!(provider.set_password("alice", "changed"), PasswordUpdate::Unsupported);
+    }
+
+    #[test]
+    fn empty_password_never_reaches_the_server() {
+        let provider = LdapProvider::new(stand_in_server(0), DN.to_string(), true);
+        assert_eq!(provider.authenticate("alice", ""), Authentication::WrongPassword);
+    }
+
+    #[test]
+    fn unreachable_server_is_unavailable() {
+        let address = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+        let provider = LdapProvider::new(address, DN.to_string(), true);
+        assert_eq!(provider.authenticate("alice", "secret"), Authentication::Unavailable);
+    }
+
+    #[test]
+    fn plain_ldap_is_refused_unless_allowed() {
+        let provider = LdapProvider::new(stand_in_server(0), DN.to_string(), false);
+        assert_eq!(provider.authenticate("alice", "secret"), Authentication::Unavailable);
+    }
+
+    #[test]
+    fn parses_addresses() {
+        let provider = LdapProvider::new("ldaps://ldap.example.org".to_string(), DN.to_string(), false);
+        assert_eq!((provider.address.as_str(), provider.host.as_str(), provider.tls.is_some()), ("ldap.example.org:636", "ldap.example.org", true));
+        let provider = LdapProvider::new("ldap://[::1]:1389/".to_string(), DN.to_string(), false);
+        assert_eq!((provider.address.as_str(), provider.host.as_str(), provider.tls.is_some()), ("[::1]:1389", "::1", false));
+        let provider = LdapProvider::new("127.0.0.1:389".to_string(), DN.to_string(), false);
+        assert_eq!((provider.address.as_str(), provider.tls.is_some()), ("127.0.0.1:389", false));
+    }
+
+    #[test]
+    fn escapes_dn_values() {
+        let provider = LdapProvider::new(String::new(), DN.to_string(), true);
+        assert_eq!(provider.dn("a,b=c"), "uid=a\\,b\\=c,ou=people,dc=test");
+        assert_eq!(escape_dn_value("#x "), "\\#x\\ ");
+    }
+
+    #[test]
+    fn long_lengths_round_trip() {
+        let password = "p".repeat(300);
+        let request = bind_request(BIND_MESSAGE_ID, "uid=x", &password);
+        assert_eq!(parse_bind_request(&request), Some(("uid=x".to_string(), password)));
+    }
+}
@@ -1,45 +1,214 @@
-use std::collections::HashMap;
+use std::{net::IpAddr, sync::RwLock};
 
 use rocket::http::Status;
 use rocket::request::Request;
-use rocket::data::{self, Data, FromData};
+use rocket::data::{self, Data, FromData, ToByteUnit};
 use rocket::outcome::Outcome;
 use serde::{Deserialize, Serialize};
 
-use crate::{database::Database, soterius, user::User};
+use crate::{access::{self, Access, Target}, apikey::{self, ApiKey}, config, database::Database, identity::{self, Authentication}, soterius, throttle, totp, user::Role, utils};
+
+pub const LOGIN_BODY_LIMIT: usize = 4096;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct LoginInformation {
     pub username: String,
-    pub password: String
+    pub password: String,
+    // the authenticator or recovery code, only read by /2fa/session
+    #[serde(default)]
+    pub otp: Option<String>,
+    // the token /2fa/session handed out, needed on every other request once 2fa is enabled
+    #[serde(default)]
+    pub session: Option<String>,
+
+    #[serde(skip)]
+    pub ip: Option<IpAddr>,
+    // taken from an `Authorization: Bearer` header, in which case there is no body
+    #[serde(skip)]
+    pub api_key: Option<String>
 }
 impl LoginInformation {
     // handles anything to do with password or logging in
-    pub fn login(&self, account_handler: &mut Database) -> LoginResult {
-        // check soterius if it exists first
+    // takes the lock itself so the password check never holds it, callers lock afterwards for the actual work
+    pub fn login(&self, account_handler: &RwLock<Database>) -> LoginResult {
+        self.login_with(account_handler, false)
+    }
+
+    // like login, but checks the otp code instead of a session token, see totp::session
+    pub fn login_with_code(&self, account_handler: &RwLock<Database>) -> LoginResult {
+        self.login_with(account_handler, true)
+    }
+
+    fn login_with(&self, account_handler: &RwLock<Database>, with_code: bool) -> LoginResult {
+        if let Some(wait) = throttle::get().lock().unwrap().check(&self.username, self.ip, utils::get_time()) {
+            return LoginResult::TooManyAttempts(wait);
+        }
+
+        let result = match &self.api_key {
+            Some(key) => self.check_key(key, account_handler),
+            None => match self.check_credentials(account_handler) {
+                LoginResult::Success(user_id) if with_code => self.check_code(user_id, account_handler),
+                LoginResult::Success(user_id) => self.check_session(user_id, account_handler),
+                result => result
+            }
+        };
+        match result {
+            LoginResult::Success(_) => throttle::get().lock().unwrap().succeed(&self.username),
+            LoginResult::UsernameNoExist | LoginResult::PasswordWrong | LoginResult::SecondFactorWrong | LoginResult::SessionInvalid | LoginResult::KeyInvalid => throttle::get().lock().unwrap().fail(&self.username, self.ip, utils::get_time()),
+            _ => {}
+        }
+
+        match result {
+            LoginResult::UsernameNoExist | LoginResult::PasswordWrong if config::get().generic_login_failure => LoginResult::InvalidCredentials,
+            _ => result
+        }
+    }
+
+    fn check_credentials(&self, account_handler: &RwLock<Database>) -> LoginResult {
+        // check the identity provider first
 
         // if exists:
         //      if exists in aurum -> get user id and proceed
-        //      if not exists in aurum -> generate new user id and proceed
+        //      if not exists in aurum -> take the provider's id, or generate one if it has none, and proceed
         // if doesnt exist -> return username no exist
 
-        match soterius::fetch(self.username.clone()) {
-            Some((user_id, password)) => {
-                if password != self.password {
-                    return LoginResult::PasswordWrong;
+        match identity::get().authenticate(&self.username, &self.password) {
+            Authentication::Success(provider_id) => {
+                let athena_lookup = account_handler.read().unwrap().fetch_user_id(&self.username);
+                match athena_lookup {
+                    Some(user_id) => LoginResult::Success(user_id),
+                    None => {
+                        let mut db = account_handler.write().unwrap();
+                        let user_id = provider_id.unwrap_or_else(|| utils::generate_id(db.users.keys().copied().collect::<Vec<u128>>(), soterius::USER_ID_MAX));
+                        db.insert_user(user_id, self.username.clone());
+                        LoginResult::Success(user_id)
+                    }
                 }
+            },
+            Authentication::UnknownUser => LoginResult::UsernameNoExist,
+            Authentication::WrongPassword => LoginResult::PasswordWrong,
+            Authentication::Unavailable => LoginResult::ProviderUnavailable
+        }
+    }
+
+    // only after the password was right, api keys skip this as minting one already took both factors
+    // codes are checked once per session rather than per request, each accepted one is used up and saved
+    fn check_code(&self, user_id: u128, account_handler: &RwLock<Database>) -> LoginResult {
+        if !account_handler.read().unwrap().second_factors.get(&user_id).is_some_and(|f| f.enabled) {
+            return LoginResult::Success(user_id);
+        }
+        let code = match &self.otp {
+            Some(c) => c,
+            None => return LoginResult::SecondFactorRequired
+        };
+
+        let mut db = account_handler.write().unwrap();
+        let accepted = match db.second_factors.get_mut(&user_id) {
+            Some(f) => f.verify(&self.username, code, utils::get_time() as u64),
+            None => true // disabled in the meantime
+        };
+        if accepted {
+            db.save();
+            LoginResult::Success(user_id)
+        } else {
+            LoginResult::SecondFactorWrong
+        }
+    }
+
+    fn check_session(&self, user_id: u128, account_handler: &RwLock<Database>) -> LoginResult {
+        if !account_handler.read().unwrap().second_factors.get(&user_id).is_some_and(|f| f.enabled) {
+            return LoginResult::Success(user_id);
+        }
+        match &self.session {
+            Some(token) if totp::sessions().lock().unwrap().check(token, user_id, utils::get_time()) => LoginResult::Success(user_id),
+            Some(_) => LoginResult::SessionInvalid,
+            None => LoginResult::SecondFactorRequired
+        }
+    }
+
+    fn check_key(&self, key: &str, account_handler: &RwLock<Database>) -> LoginResult {
+        let now = utils::get_time();
+        let (key_id, user_id, stale) = {
+            let db = account_handler.read().unwrap();
+            match ApiKey::verify(&db, key) {
+                Ok(key_id) => {
+                    let api_key = &db.api_keys[&key_id];
+                    (key_id, api_key.user_id, api_key.last_used.is_none_or(|t| now.saturating_sub(t) >= apikey::LAST_USED_GRANULARITY))
+                },
+                Err(e) => return e
+            }
+        };
+        // kept in memory only, it goes to disk with the next save instead of costing a write per request
+        if stale {
+            if let Some(api_key) = account_handler.write().unwrap().api_keys.get_mut(&key_id) {
+                api_key.last_used = Some(now);
+            }
+        }
+        LoginResult::Success(user_id)
+    }
+
+    // login plus the user's permissions on the target's project, and the key's scope for key logins
+    pub fn authorize(&self, account_handler: &RwLock<Database>, access: Access, target: Target) -> LoginResult {
+        let result = self.login(account_handler);
+        let user_id = match result {
+            LoginResult::Success(user_id) => user_id,
+            _ => return result
+        };
+
+        let db = account_handler.read().unwrap();
+        if let Some(key) = &self.api_key {
+            match ApiKey::verify(&db, key).ok().and_then(|i| db.api_keys.get(&i)) {
+                Some(k) if k.scope.allows(&db, access, target) => {},
+                _ => return LoginResult::OutOfScope
+            }
+        }
 
-                let athena_lookup = account_handler.fetch_user_id(&self.username);
-                if athena_lookup.is_none() {
-                    account_handler.users.insert(user_id, User {
-                        id: user_id,
-                        username: self.username.clone()
-                    });
+        if target == Target::Global {
+            return result;
+        }
+        match target.project(&db) {
+            Some(project_id) if access::project_permissions(&db, user_id, project_id).allows(access.required()) => {
+                if !access.allowed_on_archived() && db.projects.get(&project_id).is_some_and(|p| p.archived) {
+                    return LoginResult::ProjectArchived;
                 }
+                result
+            },
+            _ => LoginResult::PermissionDenied
+        }
+    }
+
+    pub fn parse(body: &str) -> Result<LoginInformation, LoginInfoParseError> {
+        if body.trim().is_empty() {
+            return Err(LoginInfoParseError::Empty);
+        }
+
+        let raw: RawLoginInformation = serde_json::from_str(body).map_err(|_| LoginInfoParseError::ParsingError)?;
+        Ok(LoginInformation {
+            username: raw.username.ok_or(LoginInfoParseError::MissingUsername)?,
+            password: raw.password.ok_or(LoginInfoParseError::MissingPassword)?,
+            otp: raw.otp,
+            session: raw.session,
+            ip: None,
+            api_key: None
+        })
+    }
 
-                return LoginResult::Success(user_id);
+    // same as login, but only lets through server admins
+    // usernames listed in the config are always admins, so there is a way in before anyone has the role
+    pub fn login_admin(&self, account_handler: &RwLock<Database>) -> LoginResult {
+        if self.api_key.is_some() {
+            return LoginResult::KeyNotAllowed;
+        }
+        match self.login(account_handler) {
+            LoginResult::Success(user_id) => {
+                let has_role = account_handler.read().unwrap().users.get(&user_id).is_some_and(|u| u.role == Role::Admin);
+                if has_role || config::get().is_admin(&self.username) {
+                    LoginResult::Success(user_id)
+                } else {
+                    LoginResult::NotAdmin
+                }
             },
-            None => LoginResult::UsernameNoExist
+            result => result
         }
     }
 }
@@ -48,34 +217,77 @@ impl LoginInformation {
 impl<'l> FromData<'l> for LoginInformation {
     type Error = LoginInfoParseError;
 
-    async fn from_data(_req: &'l Request<'_>, mut data: Data<'l>) -> data::Outcome<'l, Self> {
-        let result = data.peek(512).await.to_vec();
+    async fn from_data(req: &'l Request<'_>, data: Data<'l>) -> data::Outcome<'l, Self> {
+        if let Some(key) = req.headers().get_one("Authorization").and_then(|h| h.strip_prefix("Bearer ")) {
+            return Outcome::Success(LoginInformation {
+                username: String::new(),
+                password: String::new(),
+                otp: None,
+                session: None,
+                ip: req.client_ip(),
+                api_key: Some(key.trim().to_string())
+            });
+        }
+
+        let result = LoginInformation::read_body(req, data).await
+            .and_then(|body| LoginInformation::parse(body.as_str()))
+            .map(|login| LoginInformation { ip: req.client_ip(), ..login });
+        LoginInformation::outcome(req, result)
+    }
+}
+impl LoginInformation {
+    // shared by every body that carries login details
+    pub async fn read_body(req: &Request<'_>, data: Data<'_>) -> Result<String, LoginInfoParseError> {
+        // the limit can be raised through rocket's `limits.login` config key
+        let limit = req.limits().get("login").unwrap_or(LOGIN_BODY_LIMIT.bytes());
 
-        if result.is_empty() {
-            // return OutCome::Error
-            return Outcome::Error((
-                Status::Ok,
-                LoginInfoParseError::Empty
-            ))
+        match data.open(limit).into_string().await {
+            Ok(body) if !body.is_complete() => Err(LoginInfoParseError::TooLarge),
+            Ok(body) => Ok(body.into_inner()),
+            Err(_) => Err(LoginInfoParseError::ParsingError) // not utf-8, or the connection dropped
         }
-        
-        let result = result.iter().map(|x| (x.clone()) as char).collect::<String>();
-        let result: HashMap<String, String> = serde_json::from_str(result.as_str()).unwrap();
+    }
 
-        Outcome::Success(LoginInformation {
-            username: result.get("username").unwrap().clone(),
-            password: result.get("password").unwrap().clone()
-        })
+    pub fn outcome<'l, T>(req: &'l Request<'_>, result: Result<T, LoginInfoParseError>) -> data::Outcome<'l, T, LoginInfoParseError> {
+        match result {
+            Ok(t) => Outcome::Success(t),
+            Err(e) => {
+                // picked up again by the 400 catcher below, which has no other way of seeing the error
+                req.local_cache(|| Some(e));
+                Outcome::Error((Status::BadRequest, e))
+            }
+        }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Deserialize)]
+struct RawLoginInformation {
+    username: Option<String>,
+    password: Option<String>,
+    otp: Option<String>,
+    session: Option<String>
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum LoginInfoParseError {
     Success,
 
     ParsingError,
 
-    Empty
+    Empty,
+    TooLarge,
+
+    MissingUsername,
+    MissingPassword,
+    MissingNewPassword
+}
+
+#[catch(400)]
+pub fn bad_request(req: &Request) -> String {
+    match req.local_cache(|| None::<LoginInfoParseError>) {
+        Some(e) => utils::parse_response(Err(*e)),
+        None => utils::parse_response(Err(LoginInfoParseError::ParsingError))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -87,4 +299,31 @@ pub enum LoginResult {
     PasswordWrong,
 
     UsernameTaken,
+
+    NotAdmin,
+
+    // seconds until the next attempt is accepted
+    TooManyAttempts(u128),
+    // stands in for UsernameNoExist and PasswordWrong when generic_login_failure is on
+    InvalidCredentials,
+
+    ProviderUnavailable,
+
+    // the password was right, but the account needs a session from /2fa/session as well
+    SecondFactorRequired,
+    SecondFactorWrong,
+    // unknown or expired, a new one has to be opened with a code
+    SessionInvalid,
+
+    KeyInvalid,
+    KeyExpired,
+    // the route needs a password login, e.g. admin routes or managing keys
+    KeyNotAllowed,
+    // the key is read-only or limited to another project
+    OutOfScope,
+
+    // the user's role on the project isn't enough, or the project doesn't exist
+    PermissionDenied,
+    // archived projects only take reads, unarchiving and deletion
+    ProjectArchived,
 }
\ No newline at end of file
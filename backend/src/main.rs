@@ -1,22 +1,46 @@
-use std::sync::Mutex;
+use std::sync::{Arc, RwLock};
+
+use fsck::FsckMode;
+use rocket::{Build, Rocket};
 
 use project::fetch_by_ownership;
 
 #[macro_use] extern crate rocket;
 
 mod utils;
+mod config;
 mod cors;
+mod cli;
+mod bench;
 
 mod soterius;
+mod identity;
+mod ldap;
 
 mod database;
+mod indices;
+mod backup;
+mod fsck;
 mod login_info;
+mod access;
+mod apikey;
+mod totp;
+mod account;
+mod throttle;
 mod user;
 mod team;
 
 mod project;
 mod group;
 mod task;
+mod share;
+mod events;
+mod webhook;
+mod activity;
+mod trash;
+mod search;
+mod filter;
+mod paging;
 
 
 #[get("/")]
@@ -24,24 +48,96 @@ pub fn index() -> String {
     "can you understand me?".to_string()
 }
 
-#[launch]
-fn rocket() -> _ {
-    rocket::custom(rocket::config::Config::figment().merge(("port", 8002)))
-        .manage(Mutex::new(database::Database::load()))
+#[rocket::main]
+async fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    if !args.is_empty() {
+        cli::run(&args);
+        return;
+    }
+
+    let mut db = database::Database::load();
+    match config::get().fsck {
+        FsckMode::Off => {},
+        FsckMode::Check => fsck::print_report(&fsck::check(&db), false),
+        FsckMode::Repair => fsck::print_report(&fsck::repair(&mut db), true)
+    }
+
+    let db = Arc::new(RwLock::new(db));
+    database::Database::persist(db.clone());
+    backup::Snapshot::schedule(db.clone());
+    webhook::Webhook::dispatch(db.clone());
+    trash::Trash::schedule(db.clone());
+
+    if let Err(e) = rocket(db.clone()).launch().await {
+        println!("{e}");
+    }
+    // anything still queued for the persister when rocket shut down
+    db.read().unwrap().save_now();
+}
+
+fn rocket(db: Arc<RwLock<database::Database>>) -> Rocket<Build> {
+    let mut rocket = rocket::custom(config::Config::figment());
+    if config::get().admin_routes {
+        rocket = rocket
+            .mount("/save", routes![database::save])
+            .mount("/load", routes![database::load])
+            .mount("/debug", routes![database::debug]);
+    }
+
+    rocket
+        .manage(db)
         .mount("/", routes![index])
-        .mount("/save", routes![database::save])
-        .mount("/load", routes![database::load])
-        .mount("/debug", routes![database::debug])
+        .mount("/backup/list", routes![backup::list])
+        .mount("/backup/take", routes![backup::take])
+        .mount("/backup/restore", routes![backup::restore])
 
         .mount("/project/fetch", routes![project::fetch])
         .mount("/project/fetch_by_ownership", routes![fetch_by_ownership])
         .mount("/project/create", routes![project::create])
         .mount("/project/delete", routes![project::delete])
         .mount("/project/edit", routes![project::edit])
+        .mount("/project/archive", routes![project::archive])
+        .mount("/project/unarchive", routes![project::unarchive])
+        .mount("/project/transfer", routes![project::transfer])
+        .mount("/project/accept_transfer", routes![project::accept_transfer])
+        .mount("/project/members", routes![project::members])
+        .mount("/project/set_member", routes![project::set_member])
+        .mount("/project/remove_member", routes![project::remove_member])
+
+        .mount("/share/create", routes![share::create])
+        .mount("/share/list", routes![share::list])
+        .mount("/share/revoke", routes![share::revoke])
+        .mount("/shared", routes![share::fetch, share::fetch_with_password])
+
+        .mount("/events", routes![events::subscribe])
+
+        .mount("/webhook/create", routes![webhook::create])
+        .mount("/webhook/list", routes![webhook::list])
+        .mount("/webhook/delete", routes![webhook::delete])
+        .mount("/webhook/deliveries", routes![webhook::deliveries])
+        .mount("/webhook/test", routes![webhook::test])
+
+        .mount("/activity/project", routes![activity::project])
+        .mount("/activity/task", routes![activity::task])
+        .mount("/activity/user", routes![activity::user])
+
+        .mount("/trash/list", routes![trash::list])
+        .mount("/trash/restore", routes![trash::restore])
+
+        .mount("/search", routes![search::search])
+
+        .mount("/filter/run", routes![filter::run])
+        .mount("/filter/run_saved", routes![filter::run_saved])
+        .mount("/filter/save", routes![filter::save])
+        .mount("/filter/list", routes![filter::list])
+        .mount("/filter/delete", routes![filter::delete])
 
         .mount("/group/create", routes![group::create])
         .mount("/group/delete", routes![group::delete])
         .mount("/group/edit", routes![group::edit])
+        .mount("/group/archive", routes![group::archive])
+        .mount("/group/unarchive", routes![group::unarchive])
 
         .mount("/task/create", routes![task::create])
         .mount("/task/delete", routes![task::delete])
@@ -50,8 +146,32 @@ fn rocket() -> _ {
         .mount("/task/toggle_assign", routes![task::toggle_assign])
         .mount("/task/complete", routes![task::complete])
         .mount("/task/toggle_complete", routes![task::toggle_complete])
+        .mount("/task/label", routes![task::label])
+        .mount("/task/set_due", routes![task::set_due])
 
         .mount("/user/fetch_teams", routes![user::fetch_teams])
+        .mount("/user/set_role", routes![user::set_role])
+
+        .mount("/account/register", routes![account::register, account::register_with_invite])
+        .mount("/account/invite", routes![account::invite])
+        .mount("/account/change_password", routes![account::change_password])
+        .mount("/account/reset_password", routes![account::reset_password])
+
+        .mount("/key/create", routes![apikey::create])
+        .mount("/key/list", routes![apikey::list])
+        .mount("/key/revoke", routes![apikey::revoke])
+
+        .mount("/2fa/enroll", routes![totp::enroll])
+        .mount("/2fa/confirm", routes![totp::confirm])
+        .mount("/2fa/session", routes![totp::session])
+        .mount("/2fa/recovery_codes", routes![totp::recovery_codes])
+        .mount("/2fa/disable", routes![totp::disable])
+        .mount("/2fa/reset", routes![totp::reset])
+
+        .mount("/throttle/blocked", routes![throttle::blocked])
+        .mount("/throttle/unblock", routes![throttle::unblock_username, throttle::unblock_ip, throttle::unblock_share_link])
+
+        .register("/", catchers![login_info::bad_request])
 
-        .attach(cors::CORS)
+        .attach(cors::Cors)
 }
\ No newline at end of file
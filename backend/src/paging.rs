@@ -0,0 +1,151 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use strum_macros::EnumString;
+
+pub const DEFAULT_LIMIT: usize = 50;
+pub const MAX_LIMIT: usize = 200;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum PageError {
+    SortInvalid,
+    // not one handed out by us, or from a listing sorted differently
+    CursorInvalid
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default, EnumString)]
+pub enum Sort {
+    #[default]
+    #[strum(ascii_case_insensitive)]
+    Name,
+    #[strum(ascii_case_insensitive)]
+    Created,
+    #[strum(ascii_case_insensitive)]
+    Updated
+}
+
+// what an entry is sorted by, ties are broken by id so the order is total and stays put between requests
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Key {
+    Text(String),
+    Time(u128)
+}
+
+// query parameters of the list endpoints, e.g. ?sort=created&desc=true&limit=20&cursor=...
+#[derive(FromForm, Default, Debug)]
+pub struct Paging {
+    pub sort: Option<String>,
+    pub desc: Option<bool>,
+    // the `next` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<usize>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Page<T> {
+    pub items: Vec<(u128, T)>,
+    // None on the last page
+    pub next: Option<String>
+}
+
+// the cursor is the position of the last entry handed out, hex so it can go into a url as it is
+fn encode(position: &(Key, u128)) -> String {
+    serde_json::to_vec(position).unwrap().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode(cursor: &str) -> Option<(Key, u128)> {
+    if !cursor.len().is_multiple_of(2) || !cursor.is_ascii() {
+        return None;
+    }
+    let bytes = (0..cursor.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&cursor[i..i + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+impl Paging {
+    pub fn sort(&self) -> Result<Sort, PageError> {
+        match &self.sort {
+            Some(s) => Sort::from_str(s).map_err(|_| PageError::SortInvalid),
+            None => Ok(Sort::default())
+        }
+    }
+
+    // sorts the entries by `key` and cuts out the page after the cursor
+    pub fn apply<T>(&self, entries: Vec<(u128, T)>, key: impl Fn(Sort, u128, &T) -> Key) -> Result<Page<T>, PageError> {
+        let sort = self.sort()?;
+        let desc = self.desc.unwrap_or(false);
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+        let after = match &self.cursor {
+            Some(c) => match decode(c) {
+                Some(p) if matches!(p.0, Key::Text(_)) == (sort == Sort::Name) => Some(p),
+                _ => return Err(PageError::CursorInvalid)
+            },
+            None => None
+        };
+
+        let mut keyed = entries.into_iter()
+            .map(|(id, item)| ((key(sort, id, &item), id), item))
+            .collect::<Vec<((Key, u128), T)>>();
+        keyed.sort_by(|(a, _), (b, _)| if desc { b.cmp(a) } else { a.cmp(b) });
+
+        let mut rest = keyed.into_iter().filter(|(position, _)| match &after {
+            Some(a) if desc => position < a,
+            Some(a) => position > a,
+            None => true
+        });
+        let page = rest.by_ref().take(limit).collect::<Vec<((Key, u128), T)>>();
+        let next = match rest.next() {
+            Some(_) => page.last().map(|(position, _)| encode(position)),
+            None => None
+        };
+        Ok(Page {
+            items: page.into_iter().map(|((_, id), item)| (id, item)).collect(),
+            next
+        })
+    }
+}
+
+// names compare without case so "apple" and "Banana" sort the way people expect
+pub fn name_key(name: &str) -> Key {
+    Key::Text(name.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{name_key, Key, PageError, Paging, Sort};
+
+    fn page(paging: &Paging) -> (Vec<u128>, Option<String>) {
+        let entries = vec![(1, "delta"), (2, "Alpha"), (3, "charlie"), (4, "bravo"), (5, "alpha")];
+        let page = paging.apply(entries, |sort, id, name| match sort {
+            Sort::Name => name_key(name),
+            _ => Key::Time(10 - id)
+        }).unwrap();
+        (page.items.into_iter().map(|(i, _)| i).collect(), page.next)
+    }
+
+    #[test]
+    fn walks_pages_in_a_stable_order() {
+        let (first, next) = page(&Paging { limit: Some(2), ..Paging::default() });
+        // the two alphas tie, the id decides
+        assert_eq!(first, vec![2, 5]);
+        let (second, next) = page(&Paging { limit: Some(2), cursor: next, ..Paging::default() });
+        assert_eq!(second, vec![4, 3]);
+        let (last, next) = page(&Paging { limit: Some(2), cursor: next, ..Paging::default() });
+        assert_eq!(last, vec![1]);
+        assert_eq!(next, None);
+
+        let created = Paging { sort: Some("CREATED".to_string()), desc: Some(true), limit: Some(3), ..Paging::default() };
+        let (first, next) = page(&created);
+        assert_eq!(first, vec![1, 2, 3]);
+        assert_eq!(page(&Paging { cursor: next.clone(), ..created }).0, vec![4, 5]);
+
+        // a cursor from one sort doesn't fit another
+        let mismatched = Paging { cursor: next, ..Paging::default() };
+        assert_eq!(mismatched.apply(vec![(1, ())], |_, _, _| Key::Time(0)).err(), Some(PageError::CursorInvalid));
+        let garbage = Paging { cursor: Some("zz".to_string()), ..Paging::default() };
+        assert_eq!(garbage.apply(vec![(1, ())], |_, _, _| Key::Time(0)).err(), Some(PageError::CursorInvalid));
+        let unknown = Paging { sort: Some("size".to_string()), ..Paging::default() };
+        assert_eq!(unknown.apply(vec![(1, ())], |_, _, _| Key::Time(0)).err(), Some(PageError::SortInvalid));
+    }
+}
@@ -1,85 +1,270 @@
-use std::{collections::HashMap, fs, str::FromStr, sync::Mutex};
+use std::{collections::{BTreeMap, HashMap}, fs, path::PathBuf, str::FromStr, sync::{Arc, RwLock}};
 
-use rand::Rng;
 use rocket::State;
 use serde::{Deserialize, Serialize};
 use strum_macros::EnumString;
 
-use crate::{database::Database, group::{self, Group}, login_info::{LoginInformation, LoginResult}, user::User, utils};
+use crate::{access::{self, Access, Target}, activity::Activity, config, database::Database, events::{self, Change}, group::Group, login_info::{LoginInformation, LoginResult}, paging::{self, Key, Paging, Sort}, search::SearchIndex, share::ShareLink, team::Permissions, trash::Trash, utils};
 
 pub const PROJECT_ID_MAX: u128 = 4294967296u128; // 16^8, 2^32
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ProjectError {
+    ProjectNoExist,
+    OwnerNoExist,
+
+    // the caller needs to be admin of the project to give it away
+    NotProjectAdmin,
+    // creating for or handing over to a team needs Editor or Admin there
+    NotTeamEditor,
+
+    // the project wasn't offered to the caller, or the offer was withdrawn
+    TransferNotOffered,
+
+    OwnerTypeInvalid,
+    PermissionsInvalid,
+    UserNoExist
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Project {
     pub name: String,
 
     pub owner: Ownership,
 
-    pub groups: Vec<u128>
+    pub groups: Vec<u128>,
+
+    // per user entries that take precedence over the owning team's roles, and can let in people outside it
+    #[serde(default)]
+    pub members: HashMap<u128, Permissions>,
+
+    // hidden from listings and read-only until unarchived
+    #[serde(default)]
+    pub archived: bool,
+
+    // handing a project to another user only offers it, it changes hands once they accept
+    #[serde(default)]
+    pub offered_to: Option<u128>,
+
+    // kept up to date by the domain functions, see touch
+    #[serde(default)]
+    pub created_at: u128,
+    // None for anything from before authors were tracked that the activity log knows nothing about
+    #[serde(default)]
+    pub created_by: Option<u128>,
+    #[serde(default)]
+    pub updated_at: u128,
+    #[serde(default)]
+    pub updated_by: Option<u128>
 }
 impl Project {
-    pub fn save(db: &Database) {
-        fs::write("data/projects.json", serde_json::to_string_pretty(&db.projects).unwrap()).unwrap();
+    pub fn serialize(db: &Database) -> (PathBuf, String) {
+        (config::get().data_path("projects.json"), serde_json::to_string_pretty(&db.projects).unwrap())
     }
 
     pub fn load() -> HashMap<u128, Project> {
-        serde_json::from_str(fs::read_to_string("data/projects.json").unwrap().as_str()).unwrap()
+        serde_json::from_str(fs::read_to_string(config::get().data_path("projects.json")).unwrap().as_str()).unwrap()
     }
 
     pub fn parent_of_group(db: &Database, group_id: u128) -> Option<u128> {
-        for (i, p) in &db.projects {
-            if p.groups.contains(&group_id) {
-                return Some(*i);
+        db.index.group_project.get(&group_id).copied()
+    }
+
+    // records who changed the project last and when, called by everything that edits it
+    pub fn touch(&mut self, actor: u128) {
+        self.updated_at = utils::get_time();
+        self.updated_by = Some(actor);
+    }
+
+    pub fn create(db: &mut Database, actor: u128, owner: Ownership, name: String) {
+        let id = utils::generate_id(db.projects.keys().copied().collect::<Vec<u128>>(), PROJECT_ID_MAX);
+        let now = utils::get_time();
+        let project = Project {
+            name,
+            owner,
+            groups: vec![],
+            members: HashMap::new(),
+            archived: false,
+            offered_to: None,
+            created_at: now,
+            created_by: Some(actor),
+            updated_at: now,
+            updated_by: Some(actor)
+        };
+        Activity::record(db, actor, Some(id), Target::Project(id), "created", None::<()>, Some(&project));
+        db.projects.insert(id, project);
+        SearchIndex::refresh(db, Target::Project(id));
+        db.save();
+    }
+
+    // checks whether `user_id` may hand projects to `owner`, or create them for it
+    pub fn check_new_owner(db: &Database, user_id: u128, owner: &Ownership) -> Result<(), ProjectError> {
+        match owner {
+            Ownership::User(u) if db.users.contains_key(u) => Ok(()),
+            Ownership::Team(t) if db.teams.contains_key(t) => {
+                if access::team_permissions(db, user_id, *t).allows(Permissions::Editor) {
+                    Ok(())
+                } else {
+                    Err(ProjectError::NotTeamEditor)
+                }
+            },
+            _ => Err(ProjectError::OwnerNoExist)
+        }
+    }
+
+    // None removes the entry, so the user falls back to their team role
+    pub fn set_member(db: &mut Database, actor: u128, project_id: u128, user_id: u128, permissions: Option<Permissions>) -> Result<(), ProjectError> {
+        if !db.users.contains_key(&user_id) {
+            return Err(ProjectError::UserNoExist);
+        }
+        let project = match db.projects.get_mut(&project_id) {
+            Some(p) => p,
+            None => return Err(ProjectError::ProjectNoExist)
+        };
+        let before = match permissions {
+            Some(p) => project.members.insert(user_id, p),
+            None => project.members.remove(&user_id)
+        };
+        project.touch(actor);
+        Activity::record(db, actor, Some(project_id), Target::Project(project_id), "member_changed", Some((user_id, before)), Some((user_id, permissions)));
+        db.save();
+        events::emit(db, Some(project_id), Change::MemberChanged { user_id, permissions });
+        Ok(())
+    }
+
+    // teams take the project straight away, as the caller is Editor there; other users are only offered it
+    // true once the project changed hands, false while it waits for accept_transfer
+    pub fn transfer(db: &mut Database, user_id: u128, project_id: u128, owner: Ownership) -> Result<bool, ProjectError> {
+        if !db.projects.contains_key(&project_id) {
+            return Err(ProjectError::ProjectNoExist);
+        }
+        if !access::project_permissions(db, user_id, project_id).allows(Permissions::Admin) {
+            return Err(ProjectError::NotProjectAdmin);
+        }
+        Project::check_new_owner(db, user_id, &owner)?;
+
+        match owner {
+            Ownership::User(u) if u != user_id && db.projects[&project_id].owner != owner => {
+                let project = db.projects.get_mut(&project_id).unwrap();
+                let before = project.offered_to.replace(u);
+                project.touch(user_id);
+                Activity::record(db, user_id, Some(project_id), Target::Project(project_id), "transfer_offered", before, Some(u));
+                db.save();
+                Ok(false)
+            },
+            _ => {
+                Project::change_owner(db, user_id, project_id, owner);
+                Ok(true)
             }
         }
-        None
     }
 
-    pub fn create(db: &mut Database, user_id: u128, name: String) {
-        let id = utils::generate_id(db.projects.keys().map(|i| *i).collect::<Vec<u128>>(), PROJECT_ID_MAX);
-        db.projects.insert(id, Project {
-            name,
-            owner: Ownership::User(user_id),
-            groups: vec![]
-        });
+    pub fn accept_transfer(db: &mut Database, user_id: u128, project_id: u128) -> Result<(), ProjectError> {
+        match db.projects.get(&project_id) {
+            Some(p) if p.offered_to == Some(user_id) => {},
+            Some(_) => return Err(ProjectError::TransferNotOffered),
+            None => return Err(ProjectError::ProjectNoExist)
+        }
+        Project::change_owner(db, user_id, project_id, Ownership::User(user_id));
+        Ok(())
+    }
+
+    // also withdraws any open offer
+    fn change_owner(db: &mut Database, actor: u128, project_id: u128, owner: Ownership) {
+        let project = db.projects.get_mut(&project_id).unwrap();
+        let before = std::mem::replace(&mut project.owner, owner.clone());
+        project.offered_to = None;
+        project.touch(actor);
+        Activity::record(db, actor, Some(project_id), Target::Project(project_id), "transferred", Some(before), Some(&owner));
         db.save();
+        events::emit(db, Some(project_id), Change::ProjectTransferred { owner });
+    }
+
+    // moves the project with everything in it to the trash
+    pub fn delete(db: &mut Database, actor: u128, project_id: u128) {
+        if db.projects.contains_key(&project_id) {
+            Trash::put(db, actor, Target::Project(project_id));
+            Project::remove(db, actor, project_id);
+        }
     }
 
-    pub fn delete(db: &mut Database, project_id: u128) {
+    // erases the project for good, the trash calls this once it has a copy
+    pub fn remove(db: &mut Database, actor: u128, project_id: u128) {
         if db.projects.contains_key(&project_id) {
             for g in db.projects.get(&project_id).unwrap().groups.clone() {
-                Group::delete(db, g);
+                Group::remove(db, actor, g);
+            }
+            let project = db.projects.remove(&project_id);
+            Activity::record(db, actor, Some(project_id), Target::Project(project_id), "deleted", project, None::<()>);
+            let links = db.share_links.iter().filter(|(_, l)| l.project_id == project_id).map(|(i, _)| *i).collect::<Vec<u128>>();
+            for l in links {
+                ShareLink::revoke(db, actor, l);
+            }
+            SearchIndex::refresh(db, Target::Project(project_id));
+            // before the webhooks go, so they still hear about it
+            events::emit(db, Some(project_id), Change::ProjectDeleted);
+            let webhooks = db.webhooks.iter().filter(|(_, w)| w.project_id == project_id).map(|(i, _)| *i).collect::<Vec<u128>>();
+            for w in webhooks {
+                db.webhooks.remove(&w);
+                db.webhook_deliveries.remove(&w);
             }
-            db.projects.remove(&project_id);
             db.save();
         }
     }
 
-    pub fn edit(db: &mut Database, project_id: u128, name: String) {
-        match db.projects.get_mut(&project_id) {
-            Some(p) => {
-                p.name = name;
-                db.save();
-            },
-            None => {}
+    pub fn edit(db: &mut Database, actor: u128, project_id: u128, name: String) {
+        if let Some(p) = db.projects.get_mut(&project_id) {
+            let before = std::mem::replace(&mut p.name, name.clone());
+            p.touch(actor);
+            Activity::record(db, actor, Some(project_id), Target::Project(project_id), "edited", Some(before), Some(&name));
+            SearchIndex::refresh(db, Target::Project(project_id));
+            db.save();
+            events::emit(db, Some(project_id), Change::ProjectEdited { name });
         }
     }
 
-    pub fn fetch(db: &Database, project_id: u128) -> Option<Project> {
+    pub fn set_archived(db: &mut Database, actor: u128, project_id: u128, archived: bool) -> Result<(), ProjectError> {
+        let project = db.projects.get_mut(&project_id).ok_or(ProjectError::ProjectNoExist)?;
+        if project.archived != archived {
+            project.archived = archived;
+            project.touch(actor);
+            Activity::record(db, actor, Some(project_id), Target::Project(project_id), if archived { "archived" } else { "unarchived" }, Some(!archived), Some(archived));
+            db.save();
+            events::emit(db, Some(project_id), Change::ProjectArchived { archived });
+        }
+        Ok(())
+    }
+
+    // `archived` picks which groups are listed, the active ones or the archived ones
+    pub fn fetch(db: &Database, project_id: u128, archived: bool) -> Option<Project> {
         match db.projects.get(&project_id) {
             Some(p) => {
-                Some(p.clone())
+                let mut project = p.clone();
+                project.groups.retain(|g| db.groups.get(g).is_some_and(|g| g.archived == archived));
+                Some(project)
             },
             None => None
         }
     }
 
-    pub fn fetch_by_ownership(db: &Database, ownership: Ownership) -> Vec<Project> {
-        db.projects.values().into_iter().filter(|i| i.owner == ownership).map(|p| p.clone()).collect::<Vec<Project>>()
+    // only the projects the user can see, unordered, the route sorts and pages them
+    pub fn fetch_by_ownership(db: &Database, user_id: u128, ownership: Ownership, archived: bool) -> Vec<(u128, Project)> {
+        db.projects.iter()
+            .filter(|(i, p)| p.owner == ownership && p.archived == archived && access::project_permissions(db, user_id, **i).allows(Permissions::Viewer))
+            .map(|(i, p)| (*i, p.clone()))
+            .collect::<Vec<(u128, Project)>>()
+    }
+
+    pub fn sort_key(sort: Sort, project: &Project) -> Key {
+        match sort {
+            Sort::Name => paging::name_key(&project.name),
+            Sort::Created => Key::Time(project.created_at),
+            Sort::Updated => Key::Time(project.updated_at)
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, EnumString)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, EnumString)]
 pub enum Ownership {
     #[strum(ascii_case_insensitive)]
     User(u128),
@@ -88,13 +273,21 @@ pub enum Ownership {
 }
 
 // #region api calls
-#[post("/<name>", data="<login>")]
-pub fn create(db: &State<Mutex<Database>>, login: LoginInformation, name: String) -> String {
-    let mut db = db.lock().unwrap();
-    let result = login.login(&mut db);
+// owned by the caller, or by `team_id` if given
+#[post("/<name>?<team_id>", data="<login>")]
+pub fn create(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, name: String, team_id: Option<u128>) -> String {
+    let result = login.authorize(db, Access::Write, Target::Global);
     match result {
         LoginResult::Success(user_id) => {
-            Project::create(&mut db, user_id, utils::decode_uri(name));
+            let mut db = db.write().unwrap();
+            let owner = match team_id {
+                Some(t) => Ownership::Team(t),
+                None => Ownership::User(user_id)
+            };
+            if let Err(e) = Project::check_new_owner(&db, user_id, &owner) {
+                return utils::parse_response(Err(e));
+            }
+            Project::create(&mut db, user_id, owner, utils::decode_uri(name));
             utils::parse_response(Ok("success".to_string()))
         },
         _ => utils::parse_response(Err(result))
@@ -102,12 +295,12 @@ pub fn create(db: &State<Mutex<Database>>, login: LoginInformation, name: String
 }
 
 #[post("/<project_id>", data="<login>")]
-pub fn delete(db: &State<Mutex<Database>>, login: LoginInformation, project_id: u128) -> String {
-    let mut db = db.lock().unwrap();
-    let result = login.login(&mut db);
+pub fn delete(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, project_id: u128) -> String {
+    let result = login.authorize(db, Access::Lifecycle, Target::Project(project_id));
     match result {
-        LoginResult::Success(_) => {
-            Project::delete(&mut db, project_id);
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            Project::delete(&mut db, user_id, project_id);
             utils::parse_response(Ok("success"))
         },
         _ => utils::parse_response(Err(result))
@@ -115,38 +308,38 @@ pub fn delete(db: &State<Mutex<Database>>, login: LoginInformation, project_id:
 }
 
 #[post("/<project_id>/<name>", data="<login>")]
-pub fn edit(db: &State<Mutex<Database>>, login: LoginInformation, project_id: u128, name: String) -> String {
-    let mut db = db.lock().unwrap();
-    let result = login.login(&mut db);
+pub fn edit(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, project_id: u128, name: String) -> String {
+    let result = login.authorize(db, Access::Write, Target::Project(project_id));
     match result {
-        LoginResult::Success(_) => {
-            Project::edit(&mut db, project_id, utils::decode_uri(name));
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            Project::edit(&mut db, user_id, project_id, utils::decode_uri(name));
             utils::parse_response(Ok("success".to_string()))
         },
         _ => utils::parse_response(Err(result))
     }
 }
 
-#[post("/<project_id>", data="<login>")]
-pub fn fetch(db: &State<Mutex<Database>>, login: LoginInformation, project_id: u128) -> String {
-    let mut db = db.lock().unwrap();
-    let result = login.login(&mut db);
+#[post("/<project_id>?<archived>", data="<login>")]
+pub fn fetch(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, project_id: u128, archived: Option<bool>) -> String {
+    let result = login.authorize(db, Access::Read, Target::Project(project_id));
     match result {
         LoginResult::Success(_) => {
+            let db = db.read().unwrap();
             utils::parse_response(Ok(
-                utils::parse_response(Ok(Project::fetch(&db, project_id)))
+                utils::parse_response(Ok(Project::fetch(&db, project_id, archived.unwrap_or(false))))
             ))
         },
         _ => utils::parse_response(Err(result))
     }
 }
 
-#[post("/<owner_type>/<owner_id>", data="<login>")]
-pub fn fetch_by_ownership(db: &State<Mutex<Database>>, login: LoginInformation, owner_type: String, owner_id: u128) -> String {
-    let mut db = db.lock().unwrap();
-    let result = login.login(&mut db);
+#[post("/<owner_type>/<owner_id>?<archived>&<paging..>", data="<login>")]
+pub fn fetch_by_ownership(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, owner_type: String, owner_id: u128, archived: Option<bool>, paging: Paging) -> String {
+    let result = login.authorize(db, Access::Read, Target::Global);
     match result {
-        LoginResult::Success(_) => {
+        LoginResult::Success(user_id) => {
+            let db = db.read().unwrap();
             let ownership = match Ownership::from_str(&owner_type) {
                 Ok(t) => match t {
                     Ownership::User(_) => Ownership::User(owner_id),
@@ -154,9 +347,155 @@ pub fn fetch_by_ownership(db: &State<Mutex<Database>>, login: LoginInformation,
                 },
                 Err(_) => return utils::parse_response(Ok(""))
             };
-            utils::parse_response(Ok(Project::fetch_by_ownership(&db, ownership)))
+            let projects = Project::fetch_by_ownership(&db, user_id, ownership, archived.unwrap_or(false));
+            match paging.apply(projects, |sort, _, p| Project::sort_key(sort, p)) {
+                Ok(page) => utils::parse_response(Ok(page)),
+                Err(e) => utils::parse_response(Err(e))
+            }
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/<project_id>", data="<login>")]
+pub fn archive(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, project_id: u128) -> String {
+    set_archived(db, login, project_id, true)
+}
+
+#[post("/<project_id>", data="<login>")]
+pub fn unarchive(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, project_id: u128) -> String {
+    set_archived(db, login, project_id, false)
+}
+
+fn set_archived(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, project_id: u128, archived: bool) -> String {
+    let result = login.authorize(db, Access::Lifecycle, Target::Project(project_id));
+    match result {
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            match Project::set_archived(&mut db, user_id, project_id, archived) {
+                Ok(_) => utils::parse_response(Ok("success")),
+                Err(e) => utils::parse_response(Err(e))
+            }
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/<project_id>/<owner_type>/<owner_id>", data="<login>")]
+pub fn transfer(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, project_id: u128, owner_type: String, owner_id: u128) -> String {
+    let result = login.authorize(db, Access::Admin, Target::Project(project_id));
+    match result {
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            let owner = match Ownership::from_str(&owner_type) {
+                Ok(Ownership::User(_)) => Ownership::User(owner_id),
+                Ok(Ownership::Team(_)) => Ownership::Team(owner_id),
+                Err(_) => return utils::parse_response(Err(ProjectError::OwnerTypeInvalid))
+            };
+            match Project::transfer(&mut db, user_id, project_id, owner) {
+                Ok(true) => utils::parse_response(Ok("success")),
+                Ok(false) => utils::parse_response(Ok("offered")),
+                Err(e) => utils::parse_response(Err(e))
+            }
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+// the caller usually has no role on the project yet, so this only needs a login
+#[post("/<project_id>", data="<login>")]
+pub fn accept_transfer(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, project_id: u128) -> String {
+    let result = login.authorize(db, Access::Write, Target::Global);
+    match result {
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            if db.projects.get(&project_id).is_some_and(|p| p.archived) {
+                return utils::parse_response(Err(LoginResult::ProjectArchived));
+            }
+            match Project::accept_transfer(&mut db, user_id, project_id) {
+                Ok(_) => utils::parse_response(Ok("success")),
+                Err(e) => utils::parse_response(Err(e))
+            }
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+// ordered by user id, the map itself would come out in a different order every run
+#[post("/<project_id>", data="<login>")]
+pub fn members(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, project_id: u128) -> String {
+    let result = login.authorize(db, Access::Read, Target::Project(project_id));
+    match result {
+        LoginResult::Success(_) => {
+            let db = db.read().unwrap();
+            utils::parse_response(Ok(db.projects.get(&project_id).map(|p| p.members.iter().collect::<BTreeMap<&u128, &Permissions>>()).unwrap_or_default()))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/<project_id>/<user_id>/<raw_permissions>", data="<login>")]
+pub fn set_member(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, project_id: u128, user_id: u128, raw_permissions: String) -> String {
+    let result = login.authorize(db, Access::Admin, Target::Project(project_id));
+    match result {
+        LoginResult::Success(actor) => {
+            let permissions = match Permissions::from_str(&raw_permissions) {
+                Ok(p) => p,
+                Err(_) => return utils::parse_response(Err(ProjectError::PermissionsInvalid))
+            };
+            let mut db = db.write().unwrap();
+            match Project::set_member(&mut db, actor, project_id, user_id, Some(permissions)) {
+                Ok(_) => utils::parse_response(Ok("success")),
+                Err(e) => utils::parse_response(Err(e))
+            }
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/<project_id>/<user_id>", data="<login>")]
+pub fn remove_member(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, project_id: u128, user_id: u128) -> String {
+    let result = login.authorize(db, Access::Admin, Target::Project(project_id));
+    match result {
+        LoginResult::Success(actor) => {
+            let mut db = db.write().unwrap();
+            match Project::set_member(&mut db, actor, project_id, user_id, None) {
+                Ok(_) => utils::parse_response(Ok("success")),
+                Err(e) => utils::parse_response(Err(e))
+            }
         },
         _ => utils::parse_response(Err(result))
     }
 }
 // #endregion
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{config, database::Database, team::{Permissions, Team}};
+
+    use super::{Ownership, Project, ProjectError};
+
+    #[test]
+    fn users_have_to_accept_a_transfer() {
+        config::init_temp();
+        let mut db = Database::default();
+        db.insert_user(1, "owner".to_string());
+        db.insert_user(2, "other".to_string());
+        db.teams.insert(3, Team { name: "team".to_string(), members: HashMap::from([(1, Permissions::Editor)]) });
+        db.projects.insert(4, Project { name: "p".to_string(), owner: Ownership::User(1), groups: vec![], members: HashMap::new(), archived: false, offered_to: None, created_at: 0, created_by: None, updated_at: 0, updated_by: None });
+
+        assert_eq!(Project::transfer(&mut db, 1, 4, Ownership::User(2)), Ok(false));
+        assert_eq!(db.projects[&4].owner, Ownership::User(1));
+        assert_eq!(Project::accept_transfer(&mut db, 1, 4), Err(ProjectError::TransferNotOffered));
+        assert_eq!(Project::accept_transfer(&mut db, 2, 4), Ok(()));
+        assert_eq!((db.projects[&4].owner.clone(), db.projects[&4].offered_to), (Ownership::User(2), None));
+
+        // the new owner hands it to a team they aren't in, then offers it back and withdraws the offer
+        assert_eq!(Project::transfer(&mut db, 2, 4, Ownership::Team(3)), Err(ProjectError::NotTeamEditor));
+        assert_eq!(Project::transfer(&mut db, 2, 4, Ownership::User(1)), Ok(false));
+        assert_eq!(Project::transfer(&mut db, 2, 4, Ownership::User(2)), Ok(true));
+        assert_eq!(Project::accept_transfer(&mut db, 1, 4), Err(ProjectError::TransferNotOffered));
+    }
+}
@@ -0,0 +1,201 @@
+use std::{collections::{BTreeMap, HashMap}, sync::{Arc, RwLock}};
+
+use rocket::State;
+use serde::{Deserialize, Serialize};
+
+use crate::{access::{self, Access, Target}, database::Database, login_info::{LoginInformation, LoginResult}, team::Permissions, utils};
+
+pub const DEFAULT_LIMIT: usize = 20;
+pub const MAX_LIMIT: usize = 100;
+
+// names and titles count for more than descriptions
+pub const NAME_WEIGHT: u32 = 3;
+pub const TEXT_WEIGHT: u32 = 1;
+
+// inverted index over project and group names and task titles and descriptions
+// like Index it is never persisted, built on load and refreshed by the domain functions after every change
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct SearchIndex {
+    // term -> document -> weighted count, sorted so prefixes are a range scan
+    postings: BTreeMap<String, HashMap<Target, u32>>,
+    // the terms each document is listed under, to take it out again
+    terms: HashMap<Target, Vec<String>>
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Hit {
+    pub entity: Target,
+    pub project_id: u128,
+    pub title: String,
+    pub score: u32
+}
+
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
+impl SearchIndex {
+    pub fn build(db: &Database) -> SearchIndex {
+        let mut result = SearchIndex::default();
+        let entities = db.projects.keys().map(|p| Target::Project(*p))
+            .chain(db.groups.keys().map(|g| Target::Group(*g)))
+            .chain(db.tasks.keys().map(|t| Target::Task(*t)))
+            .collect::<Vec<Target>>();
+        for e in entities {
+            result.insert(e, SearchIndex::document(db, e));
+        }
+        result
+    }
+
+    // the weighted terms of whatever the target currently is, empty when it doesn't exist
+    fn document(db: &Database, entity: Target) -> HashMap<String, u32> {
+        let fields = match entity {
+            Target::Project(project_id) => db.projects.get(&project_id).map(|p| vec![(p.name.as_str(), NAME_WEIGHT)]),
+            Target::Group(group_id) => db.groups.get(&group_id).map(|g| vec![(g.name.as_str(), NAME_WEIGHT)]),
+            Target::Task(task_id) => db.tasks.get(&task_id).map(|t| vec![(t.title.as_str(), NAME_WEIGHT), (t.description.as_str(), TEXT_WEIGHT)]),
+            Target::Global | Target::User(_) => None
+        };
+        let mut result = HashMap::new();
+        for (text, weight) in fields.unwrap_or_default() {
+            for term in tokenize(text) {
+                *result.entry(term).or_insert(0) += weight;
+            }
+        }
+        result
+    }
+
+    fn insert(&mut self, entity: Target, document: HashMap<String, u32>) {
+        let mut terms = vec![];
+        for (term, weight) in document {
+            self.postings.entry(term.clone()).or_default().insert(entity, weight);
+            terms.push(term);
+        }
+        if !terms.is_empty() {
+            terms.sort();
+            self.terms.insert(entity, terms);
+        }
+    }
+
+    fn remove(&mut self, entity: Target) {
+        for term in self.terms.remove(&entity).unwrap_or_default() {
+            if let Some(docs) = self.postings.get_mut(&term) {
+                docs.remove(&entity);
+                if docs.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    // called after anything that changes, creates or removes the entity
+    pub fn refresh(db: &mut Database, entity: Target) {
+        let document = SearchIndex::document(db, entity);
+        db.search.remove(entity);
+        db.search.insert(entity, document);
+    }
+
+    // every query term has to match the start of some term in the document, whole words score double
+    pub fn query(&self, query: &str) -> Vec<(Target, u32)> {
+        let mut scores: Option<HashMap<Target, u32>> = None;
+        for word in tokenize(query) {
+            let mut matches: HashMap<Target, u32> = HashMap::new();
+            for (term, docs) in self.postings.range(word.clone()..).take_while(|(t, _)| t.starts_with(&word)) {
+                let factor = if *term == word { 2 } else { 1 };
+                for (doc, weight) in docs {
+                    let score = matches.entry(*doc).or_insert(0);
+                    *score = (*score).max(weight * factor);
+                }
+            }
+            scores = Some(match scores {
+                None => matches,
+                Some(s) => s.into_iter().filter_map(|(d, score)| matches.get(&d).map(|m| (d, score + m))).collect()
+            });
+        }
+
+        let mut result = scores.unwrap_or_default().into_iter().collect::<Vec<(Target, u32)>>();
+        result.sort_by(|(a, a_score), (b, b_score)| b_score.cmp(a_score).then_with(|| a.cmp(b)));
+        result
+    }
+
+    // hits in projects the user can see, optionally only one project
+    pub fn search(db: &Database, user_id: u128, query: &str, project_id: Option<u128>, limit: usize) -> Vec<Hit> {
+        db.search.query(query).into_iter()
+            .filter_map(|(entity, score)| entity.project(db).map(|p| (entity, p, score)))
+            .filter(|(_, p, _)| project_id.is_none_or(|i| i == *p))
+            .filter(|(_, p, _)| access::project_permissions(db, user_id, *p).allows(Permissions::Viewer))
+            .take(limit)
+            .map(|(entity, project_id, score)| Hit {
+                entity,
+                project_id,
+                title: match entity {
+                    Target::Project(i) => db.projects.get(&i).map(|p| p.name.clone()),
+                    Target::Group(i) => db.groups.get(&i).map(|g| g.name.clone()),
+                    Target::Task(i) => db.tasks.get(&i).map(|t| t.title.clone()),
+                    Target::Global | Target::User(_) => None
+                }.unwrap_or_default(),
+                score
+            })
+            .collect()
+    }
+}
+
+// #region api calls
+// with a project_id only that project is searched, which is also what project scoped api keys need
+#[post("/<query>?<project_id>&<limit>", data="<login>")]
+pub fn search(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, query: String, project_id: Option<u128>, limit: Option<usize>) -> String {
+    let target = project_id.map_or(Target::Global, Target::Project);
+    let result = login.authorize(db, Access::Read, target);
+    match result {
+        LoginResult::Success(user_id) => {
+            let db = db.read().unwrap();
+            let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+            utils::parse_response(Ok(SearchIndex::search(&db, user_id, &utils::decode_uri(query), project_id, limit)))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+// #endregion
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{access::Target, database::Database, group::Group, indices::Index, project::{Ownership, Project}, task::{Species, Task}};
+
+    use super::SearchIndex;
+
+    fn entities(index: &SearchIndex, query: &str) -> Vec<Target> {
+        index.query(query).into_iter().map(|(e, _)| e).collect()
+    }
+
+    #[test]
+    fn prefix_matching_ranking_and_refresh() {
+        let mut db = Database::default();
+        db.projects.insert(1, Project { name: "Website relaunch".to_string(), owner: Ownership::User(1), groups: vec![10], members: HashMap::new(), archived: false, offered_to: None, created_at: 0, created_by: None, updated_at: 0, updated_by: None });
+        db.groups.insert(10, Group { name: "Backlog".to_string(), tasks: vec![100, 101], archived: false, created_at: 0, created_by: None, updated_at: 0, updated_by: None });
+        let task = |id: u128, title: &str, description: &str| Task { id, title: title.to_string(), description: description.to_string(), species: Species::Task(false), assigned: vec![], labels: vec![], due: None, created_at: 0, created_by: None, updated_at: 0, updated_by: None };
+        db.tasks.insert(100, task(100, "Fix login redirect", "the website sends people to /home"));
+        db.tasks.insert(101, task(101, "Website footer", "links are broken"));
+        db.index = Index::build(&db);
+        db.search = SearchIndex::build(&db);
+
+
+        // titles and names beat descriptions
+        assert_eq!(entities(&db.search, "website"), vec![Target::Project(1), Target::Task(101), Target::Task(100)]);
+        assert_eq!(entities(&db.search, "web"), vec![Target::Project(1), Target::Task(101), Target::Task(100)]);
+        // every word has to match
+        assert_eq!(entities(&db.search, "web brok"), vec![Target::Task(101)]);
+        assert_eq!(entities(&db.search, "LOGIN"), vec![Target::Task(100)]);
+        assert!(entities(&db.search, "nothing").is_empty());
+
+        db.tasks.get_mut(&101).unwrap().title = "Footer".to_string();
+        SearchIndex::refresh(&mut db, Target::Task(101));
+        db.tasks.remove(&100);
+        SearchIndex::refresh(&mut db, Target::Task(100));
+        assert_eq!(entities(&db.search, "website"), vec![Target::Project(1)]);
+        assert_eq!(db.search, SearchIndex::build(&db));
+    }
+}
@@ -0,0 +1,315 @@
+use std::{collections::HashMap, net::IpAddr, path::PathBuf, sync::{Arc, RwLock}};
+
+use rocket::{data::{self, Data, FromData}, request::Request, State};
+use serde::{Deserialize, Serialize};
+
+use crate::{access::{Access, Target}, activity::Activity, config, database::Database, group::Group, login_info::{LoginInfoParseError, LoginInformation, LoginResult}, soterius, task::Task, throttle::{self, Key}, utils};
+
+pub const SHARE_ID_MAX: u128 = 4294967296u128; // 16^8, 2^32
+pub const SECRET_LENGTH: usize = 32;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ShareError {
+    LinkInvalid,
+    LinkExpired,
+
+    PasswordRequired,
+    PasswordWrong,
+    TooManyAttempts(u128),
+
+    ProjectNoExist,
+    // expires_in so large the expiry doesn't fit
+    ExpiryInvalid
+}
+
+// read-only access to one project for people without an account
+// like api keys only a hash of the secret is kept, the link is shown once when it is made
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ShareLink {
+    pub project_id: u128,
+    pub secret_hash: String,
+    // argon2, same as account passwords
+    pub password_hash: Option<String>,
+
+    pub created_by: u128,
+    pub created_at: u128,
+    pub expires_at: Option<u128>
+}
+impl ShareLink {
+    pub fn serialize(db: &Database) -> (PathBuf, String) {
+        (config::get().data_path("share_links.json"), serde_json::to_string_pretty(&db.share_links).unwrap())
+    }
+
+    pub fn load() -> HashMap<u128, ShareLink> {
+        utils::load_or_default(config::get().data_path("share_links.json"))
+    }
+
+    // returns the link id and the token that goes into the url
+    pub fn create(db: &mut Database, project_id: u128, user_id: u128, password: Option<String>, expires_at: Option<u128>) -> (u128, String) {
+        let id = utils::generate_id(db.share_links.keys().copied().collect::<Vec<u128>>(), SHARE_ID_MAX);
+        let secret = utils::generate_token(SECRET_LENGTH);
+        db.share_links.insert(id, ShareLink {
+            project_id,
+            secret_hash: utils::hash_token(&secret),
+            password_hash: password.map(|p| soterius::hash(&p)),
+            created_by: user_id,
+            created_at: utils::get_time(),
+            expires_at
+        });
+        let after = ShareLink::list(db, project_id).into_iter().find(|l| l.id == id);
+        Activity::record(db, user_id, Some(project_id), Target::Project(project_id), "share_created", None::<()>, after);
+        db.save();
+        (id, format!("{id:x}_{secret}"))
+    }
+
+    pub fn revoke(db: &mut Database, actor: u128, share_id: u128) {
+        if let Some(l) = db.share_links.remove(&share_id) {
+            Activity::record(db, actor, Some(l.project_id), Target::Project(l.project_id), "share_revoked", Some(share_id), None::<()>);
+            db.save();
+        }
+    }
+
+    // the id in front of the secret, whether or not the token is valid
+    pub fn id_of(token: &str) -> Option<u128> {
+        token.split_once('_').and_then(|(id, _)| u128::from_str_radix(id, 16).ok())
+    }
+
+    // the link's id and its password hash if the token is valid, the password is checked with verify_password
+    // once the lock is let go as hashing it takes a while
+    pub fn open(db: &Database, token: &str) -> Result<(u128, Option<String>), ShareError> {
+        let id = ShareLink::id_of(token).ok_or(ShareError::LinkInvalid)?;
+        let secret = token.split_once('_').map_or("", |(_, s)| s);
+        let link = match db.share_links.get(&id) {
+            Some(l) if l.secret_hash == utils::hash_token(secret) => l,
+            _ => return Err(ShareError::LinkInvalid)
+        };
+
+        if link.expires_at.is_some_and(|e| e <= utils::get_time()) {
+            return Err(ShareError::LinkExpired);
+        }
+        Ok((id, link.password_hash.clone()))
+    }
+
+    // only links that have a password look at it
+    pub fn verify_password(password_hash: Option<&str>, password: Option<&String>) -> Result<(), ShareError> {
+        match (password_hash, password) {
+            (None, _) => Ok(()),
+            (Some(_), None) => Err(ShareError::PasswordRequired),
+            (Some(h), Some(p)) if soterius::verify(h, p, false) => Ok(()),
+            _ => Err(ShareError::PasswordWrong)
+        }
+    }
+
+    pub fn list(db: &Database, project_id: u128) -> Vec<ShareSummary> {
+        let mut result = db.share_links.iter()
+            .filter(|(_, l)| l.project_id == project_id)
+            .map(|(i, l)| ShareSummary {
+                id: *i,
+                has_password: l.password_hash.is_some(),
+                created_by: l.created_by,
+                created_at: l.created_at,
+                expires_at: l.expires_at
+            })
+            .collect::<Vec<ShareSummary>>();
+        result.sort_by_key(|l| (l.created_at, l.id));
+        result
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ShareSummary {
+    pub id: u128,
+    pub has_password: bool,
+    pub created_by: u128,
+    pub created_at: u128,
+    pub expires_at: Option<u128>
+}
+
+// everything a share link shows, groups in board order
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SharedProject {
+    pub name: String,
+    pub groups: Vec<(u128, Group)>,
+    pub tasks: HashMap<u128, Task>
+}
+impl SharedProject {
+    pub fn collect(db: &Database, project_id: u128) -> Option<SharedProject> {
+        let project = db.projects.get(&project_id)?;
+        let groups = project.groups.iter()
+            .filter_map(|g| db.groups.get(g).filter(|group| !group.archived).map(|group| (*g, group.clone())))
+            .collect::<Vec<(u128, Group)>>();
+        let tasks = groups.iter()
+            .flat_map(|(_, g)| g.tasks.iter())
+            .filter_map(|t| db.tasks.get(t).map(|task| (*t, task.clone())))
+            .collect::<HashMap<u128, Task>>();
+        Some(SharedProject {
+            name: project.name.clone(),
+            groups,
+            tasks
+        })
+    }
+}
+
+// login details plus the password visitors will need, if any
+pub struct ShareCreation {
+    pub login: LoginInformation,
+    pub share_password: Option<String>
+}
+
+#[derive(Deserialize)]
+struct RawShareCreation {
+    share_password: Option<String>
+}
+
+#[rocket::async_trait]
+impl<'l> FromData<'l> for ShareCreation {
+    type Error = LoginInfoParseError;
+
+    async fn from_data(req: &'l Request<'_>, data: Data<'l>) -> data::Outcome<'l, Self> {
+        let result = LoginInformation::read_body(req, data).await.and_then(|body| {
+            let login = LoginInformation::parse(body.as_str())?;
+            let raw: RawShareCreation = serde_json::from_str(body.as_str()).map_err(|_| LoginInfoParseError::ParsingError)?;
+            Ok(ShareCreation {
+                login: LoginInformation { ip: req.client_ip(), ..login },
+                share_password: raw.share_password.filter(|p| !p.is_empty())
+            })
+        });
+        LoginInformation::outcome(req, result)
+    }
+}
+
+// what visitors send for password protected links
+#[derive(Deserialize)]
+struct ShareAccess {
+    password: Option<String>
+}
+
+fn view(db: &RwLock<Database>, token: &str, password: Option<&String>, ip: Option<IpAddr>) -> String {
+    // visitors have no username, wrong passwords count against the link and the ip, made up links only the ip
+    let link = ShareLink::id_of(token).map(Key::ShareLink);
+    let ip = ip.map(Key::Ip);
+    let keys = link.iter().chain(ip.iter()).cloned().collect::<Vec<Key>>();
+    if let Some(wait) = throttle::get().lock().unwrap().check_keys(&keys, utils::get_time()) {
+        return utils::parse_response(Err(ShareError::TooManyAttempts(wait)));
+    }
+
+    let opened = ShareLink::open(&db.read().unwrap(), token);
+    let result = opened.and_then(|(id, hash)| ShareLink::verify_password(hash.as_deref(), password).map(|_| id));
+    let id = match result {
+        Ok(id) => id,
+        Err(e) => {
+            let failed = match e {
+                ShareError::PasswordWrong => keys,
+                ShareError::LinkInvalid => ip.into_iter().collect(),
+                _ => vec![]
+            };
+            throttle::get().lock().unwrap().fail_keys(failed, utils::get_time());
+            return utils::parse_response(Err(e));
+        }
+    };
+
+    let db = db.read().unwrap();
+    // revoked while the password was checked
+    let project_id = match db.share_links.get(&id) {
+        Some(l) => l.project_id,
+        None => return utils::parse_response(Err(ShareError::LinkInvalid))
+    };
+    match SharedProject::collect(&db, project_id) {
+        Some(p) => utils::parse_response(Ok(p)),
+        None => utils::parse_response(Err(ShareError::ProjectNoExist))
+    }
+}
+
+// #region api calls
+#[post("/<project_id>?<expires_in>", data="<creation>")]
+pub fn create(db: &State<Arc<RwLock<Database>>>, creation: ShareCreation, project_id: u128, expires_in: Option<u128>) -> String {
+    let result = creation.login.authorize(db, Access::Admin, Target::Project(project_id));
+    match result {
+        LoginResult::Success(user_id) => {
+            let expires_at = match expires_in.map(|e| utils::get_time().checked_add(e)) {
+                Some(None) => return utils::parse_response(Err(ShareError::ExpiryInvalid)),
+                e => e.flatten()
+            };
+            let mut db = db.write().unwrap();
+            let (id, token) = ShareLink::create(&mut db, project_id, user_id, creation.share_password, expires_at);
+            utils::parse_response(Ok((id, token)))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/<project_id>", data="<login>")]
+pub fn list(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, project_id: u128) -> String {
+    let result = login.authorize(db, Access::Admin, Target::Project(project_id));
+    match result {
+        LoginResult::Success(_) => {
+            let db = db.read().unwrap();
+            utils::parse_response(Ok(ShareLink::list(&db, project_id)))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/<share_id>", data="<login>")]
+pub fn revoke(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, share_id: u128) -> String {
+    let project_id = match db.read().unwrap().share_links.get(&share_id) {
+        Some(l) => l.project_id,
+        None => return utils::parse_response(Err(ShareError::LinkInvalid))
+    };
+    let result = login.authorize(db, Access::Admin, Target::Project(project_id));
+    match result {
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            ShareLink::revoke(&mut db, user_id, share_id);
+            utils::parse_response(Ok("success"))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+// no login on these two, the token is the credential
+#[get("/<token>")]
+pub fn fetch(db: &State<Arc<RwLock<Database>>>, ip: Option<IpAddr>, token: String) -> String {
+    view(db, &token, None, ip)
+}
+
+// the body is {"password": "..."}
+#[post("/<token>", data="<body>")]
+pub fn fetch_with_password(db: &State<Arc<RwLock<Database>>>, ip: Option<IpAddr>, token: String, body: String) -> String {
+    let password = serde_json::from_str::<ShareAccess>(&body).ok().and_then(|a| a.password);
+    view(db, &token, password.as_ref(), ip)
+}
+// #endregion
+
+#[cfg(test)]
+mod tests {
+    use crate::{database::Database, soterius, utils};
+
+    use super::{ShareError, ShareLink};
+
+    #[test]
+    fn verifies_token_password_and_expiry() {
+        let mut db = Database::default();
+        let link = |password: Option<&str>, expires_at: Option<u128>| ShareLink {
+            project_id: 5,
+            secret_hash: utils::hash_token("secret"),
+            password_hash: password.map(soterius::hash),
+            created_by: 1,
+            created_at: 0,
+            expires_at
+        };
+        db.share_links.insert(1, link(None, None));
+        db.share_links.insert(2, link(Some("hunter22"), None));
+        db.share_links.insert(3, link(None, Some(utils::get_time() - 1)));
+
+        let verify = |token: &str, password: Option<&String>| ShareLink::open(&db, token)
+            .and_then(|(id, hash)| ShareLink::verify_password(hash.as_deref(), password).map(|_| id));
+        assert_eq!(verify("1_secret", None), Ok(1));
+        assert_eq!(verify("1_guess", None), Err(ShareError::LinkInvalid));
+        assert_eq!(verify("nonsense", None), Err(ShareError::LinkInvalid));
+        assert_eq!(verify("2_secret", None), Err(ShareError::PasswordRequired));
+        assert_eq!(verify("2_secret", Some(&"wrong".to_string())), Err(ShareError::PasswordWrong));
+        assert_eq!(verify("2_secret", Some(&"hunter22".to_string())), Ok(2));
+        assert_eq!(verify("3_secret", None), Err(ShareError::LinkExpired));
+    }
+}
@@ -1,6 +1,123 @@
-use std::{collections::HashMap, fs};
+use std::{collections::HashMap, fs, path::PathBuf, sync::Mutex, time::SystemTime};
 
-pub fn fetch(username: String) -> Option<(u128, String)> {
-    let soterius: HashMap<String, (u128, String)> = serde_json::from_str(fs::read_to_string("../../data/users.json").unwrap().as_str()).unwrap();
-    soterius.get(&username).map_or_else(|| None, |e| Some((e.0.clone(), e.1.clone())))
+use argon2::{password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString}, Argon2};
+
+use crate::{identity::{Authentication, IdentityProvider, PasswordUpdate, Registration}, utils};
+
+pub const USER_ID_MAX: u128 = 4294967296u128; // 16^8, 2^32
+
+// username -> (user id, password)
+// the password is an argon2 hash for accounts made or changed through athena, older soterius entries may still be plain text
+pub type Store = HashMap<String, (u128, String)>;
+
+// a soterius style users file, used both for soterius itself and for athena's local store
+// parsed once and kept until the file's mtime changes, instead of on every login
+pub struct CredentialFile {
+    path: PathBuf,
+    allow_plain_text: bool,
+
+    cache: Mutex<Option<(SystemTime, Store)>>,
+    // serializes read-modify-write cycles on the file within this process
+    write_lock: Mutex<()>
+}
+impl CredentialFile {
+    pub fn new(path: PathBuf, allow_plain_text: bool) -> CredentialFile {
+        CredentialFile {
+            path,
+            allow_plain_text,
+            cache: Mutex::new(None),
+            write_lock: Mutex::new(())
+        }
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path).and_then(|m| m.modified()).ok()
+    }
+
+    // None if the file is missing or unreadable
+    pub fn read(&self) -> Option<Store> {
+        let modified = self.modified()?;
+        let mut cache = self.cache.lock().unwrap();
+        if let Some((at, store)) = cache.as_ref() {
+            if *at == modified {
+                return Some(store.clone());
+            }
+        }
+
+        let store: Store = serde_json::from_str(fs::read_to_string(&self.path).ok()?.as_str()).ok()?;
+        *cache = Some((modified, store.clone()));
+        Some(store)
+    }
+
+    fn write(&self, store: Store) -> bool {
+        if utils::write_atomic(&self.path, serde_json::to_string_pretty(&store).unwrap()).is_err() {
+            return false;
+        }
+        if let Some(modified) = self.modified() {
+            *self.cache.lock().unwrap() = Some((modified, store));
+        }
+        true
+    }
+}
+impl IdentityProvider for CredentialFile {
+    fn authenticate(&self, username: &str, password: &str) -> Authentication {
+        let store = match self.read() {
+            Some(s) => s,
+            None => return Authentication::Unavailable
+        };
+        match store.get(username) {
+            Some((user_id, stored)) => {
+                if verify(stored, password, self.allow_plain_text) {
+                    Authentication::Success(Some(*user_id))
+                } else {
+                    Authentication::WrongPassword
+                }
+            },
+            None => Authentication::UnknownUser
+        }
+    }
+
+    fn register(&self, username: &str, password: &str) -> Registration {
+        let _guard = self.write_lock.lock().unwrap();
+        // a store that doesn't exist yet starts out empty, but one that fails to parse is left alone
+        let mut store = match (self.modified(), self.read()) {
+            (None, _) => Store::new(),
+            (Some(_), Some(s)) => s,
+            (Some(_), None) => return Registration::Unavailable
+        };
+        if store.contains_key(username) {
+            return Registration::UsernameTaken;
+        }
+
+        let id = utils::generate_id(store.values().map(|(i, _)| *i).collect::<Vec<u128>>(), USER_ID_MAX);
+        store.insert(username.to_string(), (id, hash(password)));
+        if self.write(store) { Registration::Success(id) } else { Registration::Unavailable }
+    }
+
+    fn set_password(&self, username: &str, password: &str) -> PasswordUpdate {
+        let _guard = self.write_lock.lock().unwrap();
+        let mut store = match self.read() {
+            Some(s) => s,
+            None => return PasswordUpdate::Unavailable
+        };
+        match store.get_mut(username) {
+            Some(entry) => {
+                entry.1 = hash(password);
+                if self.write(store) { PasswordUpdate::Success } else { PasswordUpdate::Unavailable }
+            },
+            None => PasswordUpdate::UnknownUser
+        }
+    }
+}
+
+pub fn hash(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default().hash_password(password.as_bytes(), &salt).unwrap().to_string()
+}
+
+pub fn verify(stored: &str, password: &str, allow_plain_text: bool) -> bool {
+    match PasswordHash::new(stored) {
+        Ok(h) => Argon2::default().verify_password(password.as_bytes(), &h).is_ok(),
+        Err(_) => allow_plain_text && stored == password // legacy soterius entry
+    }
 }
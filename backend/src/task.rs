@@ -1,14 +1,14 @@
-use std::{collections::HashMap, fs, str::FromStr, sync::Mutex};
+use std::{collections::HashMap, fs, path::PathBuf, str::FromStr, sync::{Arc, RwLock}};
 
 use rocket::State;
 use serde::{Deserialize, Serialize};
 use strum_macros::EnumString;
 
-use crate::{database::Database, group::Group, login_info::{LoginInformation, LoginResult}, utils};
+use crate::{access::{Access, Target}, activity::Activity, config, database::Database, events::{self, Change}, group::Group, login_info::{LoginInformation, LoginResult}, search::SearchIndex, trash::Trash, utils};
 
 pub const TASK_ID_MAX: u128 = 4294967296u128; // 16^8, 2^32
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Task {
     pub id: u128,
 
@@ -16,63 +16,109 @@ pub struct Task {
     pub description: String,
     pub species: Species,
 
-    pub assigned: Vec<u128>
+    pub assigned: Vec<u128>,
+
+    #[serde(default)]
+    pub labels: Vec<String>,
+    // unix seconds
+    #[serde(default)]
+    pub due: Option<u128>,
+
+    // kept up to date by the domain functions, see touch
+    #[serde(default)]
+    pub created_at: u128,
+    // None for anything from before authors were tracked that the activity log knows nothing about
+    #[serde(default)]
+    pub created_by: Option<u128>,
+    #[serde(default)]
+    pub updated_at: u128,
+    #[serde(default)]
+    pub updated_by: Option<u128>
 }
 impl Task {
-    pub fn save(db: &Database) {
-        fs::write("data/tasks.json", serde_json::to_string_pretty(&db.tasks).unwrap()).unwrap();
+    pub fn serialize(db: &Database) -> (PathBuf, String) {
+        (config::get().data_path("tasks.json"), serde_json::to_string_pretty(&db.tasks).unwrap())
     }
 
     pub fn load() -> HashMap<u128, Task> {
-        serde_json::from_str(fs::read_to_string("data/tasks.json").unwrap().as_str()).unwrap()
-    }
-
-    pub fn create(db: &mut Database, group_id: u128, title: String, description: String, species: Species) {
-        match db.groups.get_mut(&group_id) {
-            Some(g) => {
-                let id = utils::generate_id(db.tasks.keys().map(|k| *k).collect::<Vec<u128>>(), TASK_ID_MAX);
-                g.tasks.push(id);
-                db.tasks.insert(id, Task {
-                    id,
-                    title,
-                    description,
-                    assigned: vec![],
-                    species
-                });
-                db.save();
-            },
-            None => {}
+        serde_json::from_str(fs::read_to_string(config::get().data_path("tasks.json")).unwrap().as_str()).unwrap()
+    }
+
+    pub fn create(db: &mut Database, actor: u128, group_id: u128, title: String, description: String, species: Species) {
+        if let Some(g) = db.groups.get_mut(&group_id) {
+            let id = utils::generate_id(db.tasks.keys().copied().collect::<Vec<u128>>(), TASK_ID_MAX);
+            g.tasks.push(id);
+            db.index.task_group.insert(id, group_id);
+            let now = utils::get_time();
+            let task = Task {
+                id,
+                title,
+                description,
+                assigned: vec![],
+                species,
+                labels: vec![],
+                due: None,
+                created_at: now,
+                created_by: Some(actor),
+                updated_at: now,
+                updated_by: Some(actor)
+            };
+            db.tasks.insert(id, task.clone());
+            let project_id = Target::Group(group_id).project(db);
+            Activity::record(db, actor, project_id, Target::Task(id), "created", None::<()>, Some(&task));
+            SearchIndex::refresh(db, Target::Task(id));
+            db.save();
+            events::emit(db, project_id, Change::TaskCreated { group_id, task });
         }
     }
 
-    pub fn delete(db: &mut Database, task_id: u128) {
+    pub fn delete(db: &mut Database, actor: u128, task_id: u128) {
         if db.tasks.contains_key(&task_id) {
-            match Group::parent_of_task(&db, task_id).map_or(None, |i| db.groups.get_mut(&i)) {
-                Some(g) => {
-                    let indices = g.tasks.iter().enumerate().map(|(i, e)| (i, *e)).filter(|(_, t)| *t == task_id).collect::<Vec<(usize, u128)>>();
-                    if !indices.is_empty() {
-                        g.tasks.remove(indices[0].0);
-                        db.tasks.remove(&task_id);
-                        db.save();
-                    }
-                },
-                None => {}
+            Trash::put(db, actor, Target::Task(task_id));
+            Task::remove(db, actor, task_id);
+        }
+    }
+
+    pub fn remove(db: &mut Database, actor: u128, task_id: u128) {
+        if db.tasks.contains_key(&task_id) {
+            if let Some(g) = Group::parent_of_task(db, task_id).and_then(|i| db.groups.get_mut(&i)) {
+                let indices = g.tasks.iter().enumerate().map(|(i, e)| (i, *e)).filter(|(_, t)| *t == task_id).collect::<Vec<(usize, u128)>>();
+                if !indices.is_empty() {
+                    g.tasks.remove(indices[0].0);
+                    let project_id = Target::Task(task_id).project(db);
+                    let task = db.tasks.remove(&task_id);
+                    db.index.task_group.remove(&task_id);
+                    Activity::record(db, actor, project_id, Target::Task(task_id), "deleted", task, None::<()>);
+                    SearchIndex::refresh(db, Target::Task(task_id));
+                    db.save();
+                    events::emit(db, project_id, Change::TaskDeleted { task_id });
+                }
             }
         }
     }
 
-    pub fn edit(db: &mut Database, task_id: u128, title: String, description: String) {
-        match db.tasks.get_mut(&task_id) {
-            Some(t) => {
-                t.title = title;
-                t.description = description;
-                db.save();
-            },
-            None => {}
+    pub fn edit(db: &mut Database, actor: u128, task_id: u128, title: String, description: String) {
+        if let Some(t) = db.tasks.get_mut(&task_id) {
+            let before = t.clone();
+            t.title = title.clone();
+            t.description = description.clone();
+            t.touch(actor);
+            let after = t.clone();
+            let project_id = Target::Task(task_id).project(db);
+            Activity::record(db, actor, project_id, Target::Task(task_id), "edited", Some(before), Some(after));
+            SearchIndex::refresh(db, Target::Task(task_id));
+            db.save();
+            events::emit(db, project_id, Change::TaskEdited { task_id, title, description });
         }
     }
 
 
+    // records who changed the task last and when, called by everything that edits it
+    pub fn touch(&mut self, actor: u128) {
+        self.updated_at = utils::get_time();
+        self.updated_by = Some(actor);
+    }
+
     pub fn assign(&mut self, user_id: u128, state: bool) {
         if state {
             self.assigned.push(user_id);
@@ -82,7 +128,7 @@ impl Task {
                     .iter()
                     .enumerate()
                     .filter(|(_, u)| **u == user_id)
-                    .map(|(u, _)| u.clone())
+                    .map(|(u, _)| u)
                     .collect::<Vec<usize>>()[0];
                 self.assigned.remove(target);
             }
@@ -95,7 +141,7 @@ impl Task {
                 .iter()
                 .enumerate()
                 .filter(|(_, u)| **u == user_id)
-                .map(|(u, _)| u.clone())
+                .map(|(u, _)| u)
                 .collect::<Vec<usize>>()[0];
             self.assigned.remove(target);
         } else {
@@ -103,21 +149,25 @@ impl Task {
         }
     }
 
-    pub fn complete(&mut self, state: bool) {
-        match self.species {
-            Species::Task(_) => self.species = Species::Task(state),
-            _ => {}
+    // labels are kept lowercase so filters don't have to care
+    pub fn label(&mut self, label: &str, state: bool) {
+        let label = label.trim().to_lowercase();
+        if state && !label.is_empty() && !self.labels.contains(&label) {
+            self.labels.push(label);
+        } else if !state {
+            self.labels.retain(|l| *l != label);
         }
     }
 
+    pub fn complete(&mut self, state: bool) {
+        if let Species::Task(_) = self.species { self.species = Species::Task(state) }
+    }
+
     pub fn toggle_complete(&mut self) {
-        match self.species {
-            Species::Task(s) => self.species = Species::Task(!s),
-            _ => {}
-        }
+        if let Species::Task(s) = self.species { self.species = Species::Task(!s) }
     }
 }
-#[derive(Serialize, Deserialize, Clone, EnumString)]
+#[derive(Serialize, Deserialize, Clone, Debug, EnumString)]
 pub enum Species {
     #[strum(ascii_case_insensitive)]
     Task(bool),
@@ -127,11 +177,11 @@ pub enum Species {
 
 // #region api calls
 #[post("/<group_id>/<title>/<description>/<raw_species>", data="<login>")]
-pub fn create(db: &State<Mutex<Database>>, login: LoginInformation, group_id: u128, title: String, description: String, raw_species: String) -> String {
-    let mut db = db.lock().unwrap();
-    let result = login.login(&mut db);
+pub fn create(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, group_id: u128, title: String, description: String, raw_species: String) -> String {
+    let result = login.authorize(db, Access::Write, Target::Group(group_id));
     match result {
-        LoginResult::Success(_) => {
+        LoginResult::Success(actor) => {
+            let mut db = db.write().unwrap();
             let species = match Species::from_str(&raw_species) {
                 Ok(i) => match i {
                     Species::Task(_) => Species::Task(false),
@@ -139,7 +189,7 @@ pub fn create(db: &State<Mutex<Database>>, login: LoginInformation, group_id: u1
                 },
                 Err(_) => Species::Event
             };
-            Task::create(&mut db, group_id, utils::decode_uri(title), utils::decode_uri(description), species);
+            Task::create(&mut db, actor, group_id, utils::decode_uri(title), utils::decode_uri(description), species);
             utils::parse_response(Ok("success"))
         },
         _ => utils::parse_response(Err(result))
@@ -147,12 +197,12 @@ pub fn create(db: &State<Mutex<Database>>, login: LoginInformation, group_id: u1
 }
 
 #[post("/<task_id>/<title>/<description>", data="<login>")]
-pub fn edit(db: &State<Mutex<Database>>, login: LoginInformation, task_id: u128, title: String, description: String) -> String {
-    let mut db = db.lock().unwrap();
-    let result = login.login(&mut db);
+pub fn edit(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, task_id: u128, title: String, description: String) -> String {
+    let result = login.authorize(db, Access::Write, Target::Task(task_id));
     match result {
-        LoginResult::Success(_) => {
-            Task::edit(&mut db, task_id, utils::decode_uri(title), utils::decode_uri(description));
+        LoginResult::Success(actor) => {
+            let mut db = db.write().unwrap();
+            Task::edit(&mut db, actor, task_id, utils::decode_uri(title), utils::decode_uri(description));
             utils::parse_response(Ok("success"))
         }
         _ => utils::parse_response(Err(result))
@@ -160,12 +210,12 @@ pub fn edit(db: &State<Mutex<Database>>, login: LoginInformation, task_id: u128,
 }
 
 #[post("/<task_id>", data="<login>")]
-pub fn delete(db: &State<Mutex<Database>>, login: LoginInformation, task_id: u128) -> String {
-    let mut db = db.lock().unwrap();
-    let result = login.login(&mut db);
+pub fn delete(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, task_id: u128) -> String {
+    let result = login.authorize(db, Access::Write, Target::Task(task_id));
     match result {
-        LoginResult::Success(_) => {
-            Task::delete(&mut db, task_id);
+        LoginResult::Success(actor) => {
+            let mut db = db.write().unwrap();
+            Task::delete(&mut db, actor, task_id);
             utils::parse_response(Ok("success"))
         },
         _ => utils::parse_response(Err(result))
@@ -173,70 +223,158 @@ pub fn delete(db: &State<Mutex<Database>>, login: LoginInformation, task_id: u12
 }
 
 #[post("/<task_id>/<user_id>/<state>", data="<login>")]
-pub fn assign(db: &State<Mutex<Database>>, login: LoginInformation, task_id: u128, user_id: u128, state: bool) -> String {
-    let mut db = db.lock().unwrap();
-    let result = login.login(&mut db);
+pub fn assign(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, task_id: u128, user_id: u128, state: bool) -> String {
+    let result = login.authorize(db, Access::Write, Target::Task(task_id));
     match result {
-        LoginResult::Success(_) => match db.tasks.get_mut(&task_id) {
-            Some(t) => {
-                t.assign(user_id, state);
-                db.save();
-                utils::parse_response(Ok("success"))
-            },
-            None => utils::parse_response(Ok(""))
+        LoginResult::Success(actor) => {
+            let mut db = db.write().unwrap();
+            match db.tasks.get_mut(&task_id) {
+                Some(t) => {
+                    let before = t.clone();
+                    t.assign(user_id, state);
+                    t.touch(actor);
+                    let after = t.clone();
+                    let change = Change::TaskAssigned { task_id, assigned: t.assigned.clone() };
+                    let project_id = Target::Task(task_id).project(&db);
+                    Activity::record(&mut db, actor, project_id, Target::Task(task_id), "assigned", Some(before), Some(after));
+                    db.save();
+                    events::emit(&mut db, project_id, change);
+                    utils::parse_response(Ok("success"))
+                },
+                None => utils::parse_response(Ok(""))
+            }
         },
         _ => utils::parse_response(Err(result))
     }
 }
 
 #[post("/<task_id>/<user_id>", data="<login>")]
-pub fn toggle_assign(db: &State<Mutex<Database>>, login: LoginInformation, task_id: u128, user_id: u128) -> String {
-    let mut db = db.lock().unwrap();
-    let result = login.login(&mut db);
+pub fn toggle_assign(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, task_id: u128, user_id: u128) -> String {
+    let result = login.authorize(db, Access::Write, Target::Task(task_id));
     match result {
-        LoginResult::Success(_) => match db.tasks.get_mut(&task_id) {
-            Some(t) => {
-                t.toggle_assign(user_id);
-                db.save();
-                utils::parse_response(Ok(""))
-            },
-            None => utils::parse_response(Err(""))
+        LoginResult::Success(actor) => {
+            let mut db = db.write().unwrap();
+            match db.tasks.get_mut(&task_id) {
+                Some(t) => {
+                    let before = t.clone();
+                    t.toggle_assign(user_id);
+                    t.touch(actor);
+                    let after = t.clone();
+                    let change = Change::TaskAssigned { task_id, assigned: t.assigned.clone() };
+                    let project_id = Target::Task(task_id).project(&db);
+                    Activity::record(&mut db, actor, project_id, Target::Task(task_id), "assigned", Some(before), Some(after));
+                    db.save();
+                    events::emit(&mut db, project_id, change);
+                    utils::parse_response(Ok(""))
+                },
+                None => utils::parse_response(Err(""))
+            }
         },
         _ => utils::parse_response(Err(result))
     }
 }
 
 #[post("/<task_id>/<state>", data="<login>")]
-pub fn complete(db: &State<Mutex<Database>>, login: LoginInformation, task_id: u128, state: bool) -> String {
-    let mut db = db.lock().unwrap();
-    let result = login.login(&mut db);
+pub fn complete(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, task_id: u128, state: bool) -> String {
+    let result = login.authorize(db, Access::Write, Target::Task(task_id));
     match result {
-        LoginResult::Success(_) => match db.tasks.get_mut(&task_id) {
-            Some(t) => {
-                t.complete(state);
-                db.save();
-                utils::parse_response(Ok("success"))
-            },
-            None => utils::parse_response(Err(""))
+        LoginResult::Success(actor) => {
+            let mut db = db.write().unwrap();
+            match db.tasks.get_mut(&task_id) {
+                Some(t) => {
+                    let before = t.clone();
+                    t.complete(state);
+                    t.touch(actor);
+                    let after = t.clone();
+                    let change = Change::TaskCompleted { task_id, species: t.species.clone() };
+                    let project_id = Target::Task(task_id).project(&db);
+                    Activity::record(&mut db, actor, project_id, Target::Task(task_id), "completed", Some(before), Some(after));
+                    db.save();
+                    events::emit(&mut db, project_id, change);
+                    utils::parse_response(Ok("success"))
+                },
+                None => utils::parse_response(Err(""))
+            }
         },
         _ => utils::parse_response(Err(result))
     }
 }
 
 #[post("/<task_id>", data="<login>")]
-pub fn toggle_complete(db: &State<Mutex<Database>>, login: LoginInformation, task_id: u128) -> String {
-    let mut db = db.lock().unwrap();
-    let result = login.login(&mut db);
+pub fn toggle_complete(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, task_id: u128) -> String {
+    let result = login.authorize(db, Access::Write, Target::Task(task_id));
+    match result {
+        LoginResult::Success(actor) => {
+            let mut db = db.write().unwrap();
+            match db.tasks.get_mut(&task_id) {
+                Some(t) => {
+                    let before = t.clone();
+                    t.toggle_complete();
+                    t.touch(actor);
+                    let after = t.clone();
+                    let change = Change::TaskCompleted { task_id, species: t.species.clone() };
+                    let project_id = Target::Task(task_id).project(&db);
+                    Activity::record(&mut db, actor, project_id, Target::Task(task_id), "completed", Some(before), Some(after));
+                    db.save();
+                    events::emit(&mut db, project_id, change);
+                    utils::parse_response(Ok("success"))
+                },
+                None => utils::parse_response(Err(""))
+            }
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/<task_id>/<label>/<state>", data="<login>")]
+pub fn label(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, task_id: u128, label: String, state: bool) -> String {
+    let result = login.authorize(db, Access::Write, Target::Task(task_id));
     match result {
-        LoginResult::Success(_) => match db.tasks.get_mut(&task_id) {
-            Some(t) => {
-                t.toggle_complete();
-                db.save();
-                utils::parse_response(Ok("success"))
-            },
-            None => utils::parse_response(Err(""))
+        LoginResult::Success(actor) => {
+            let mut db = db.write().unwrap();
+            match db.tasks.get_mut(&task_id) {
+                Some(t) => {
+                    let before = t.clone();
+                    t.label(&utils::decode_uri(label), state);
+                    t.touch(actor);
+                    let after = t.clone();
+                    let change = Change::TaskLabelled { task_id, labels: t.labels.clone() };
+                    let project_id = Target::Task(task_id).project(&db);
+                    Activity::record(&mut db, actor, project_id, Target::Task(task_id), "labelled", Some(before), Some(after));
+                    db.save();
+                    events::emit(&mut db, project_id, change);
+                    utils::parse_response(Ok("success"))
+                },
+                None => utils::parse_response(Err(""))
+            }
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+// unix seconds, leaving `due` out clears it
+#[post("/<task_id>?<due>", data="<login>")]
+pub fn set_due(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, task_id: u128, due: Option<u128>) -> String {
+    let result = login.authorize(db, Access::Write, Target::Task(task_id));
+    match result {
+        LoginResult::Success(actor) => {
+            let mut db = db.write().unwrap();
+            match db.tasks.get_mut(&task_id) {
+                Some(t) => {
+                    let before = t.clone();
+                    t.due = due;
+                    t.touch(actor);
+                    let after = t.clone();
+                    let project_id = Target::Task(task_id).project(&db);
+                    Activity::record(&mut db, actor, project_id, Target::Task(task_id), "due_changed", Some(before), Some(after));
+                    db.save();
+                    events::emit(&mut db, project_id, Change::TaskDueChanged { task_id, due });
+                    utils::parse_response(Ok("success"))
+                },
+                None => utils::parse_response(Err(""))
+            }
         },
         _ => utils::parse_response(Err(result))
     }
 }
-// #endregion
+// #endregion
\ No newline at end of file
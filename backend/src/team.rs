@@ -1,8 +1,9 @@
-use std::{collections::HashMap, fs};
+use std::{collections::HashMap, fs, path::PathBuf};
 
 use serde::{Deserialize, Serialize};
+use strum_macros::EnumString;
 
-use crate::database::Database;
+use crate::{config, database::Database};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Team {
@@ -11,16 +12,17 @@ pub struct Team {
     pub members: HashMap<u128, Permissions>
 }
 impl Team {
-    pub fn save(db: &Database) {
-        fs::write("data/teams.json", serde_json::to_string_pretty(&db.teams).unwrap()).unwrap();
+    pub fn serialize(db: &Database) -> (PathBuf, String) {
+        (config::get().data_path("teams.json"), serde_json::to_string_pretty(&db.teams).unwrap())
     }
 
     pub fn load() -> HashMap<u128, Team> {
-        serde_json::from_str(fs::read_to_string("data/teams.json").unwrap().as_str()).unwrap()
+        serde_json::from_str(fs::read_to_string(config::get().data_path("teams.json")).unwrap().as_str()).unwrap()
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, EnumString)]
+#[strum(ascii_case_insensitive)]
 pub enum Permissions {
     Admin,
 
@@ -29,6 +31,21 @@ pub enum Permissions {
 
     None
 }
+impl Permissions {
+    fn rank(&self) -> u8 {
+        match self {
+            Permissions::Admin => 3,
+            Permissions::Editor => 2,
+            Permissions::Viewer => 1,
+            Permissions::None => 0
+        }
+    }
+
+    // true if these permissions are at least `required`
+    pub fn allows(&self, required: Permissions) -> bool {
+        self.rank() >= required.rank()
+    }
+}
 
 // #region api calls
 // #endregion
@@ -0,0 +1,252 @@
+use std::{collections::HashMap, net::IpAddr, sync::{Arc, Mutex, OnceLock, RwLock}};
+
+use rocket::State;
+use serde::{Deserialize, Serialize};
+
+use crate::{config, database::Database, login_info::{LoginInformation, LoginResult}, utils};
+
+// entries idle for longer than this many lockout periods are forgotten
+const FORGET_AFTER_LOCKOUTS: u128 = 2;
+const PRUNE_ABOVE: usize = 1024;
+
+static THROTTLE: OnceLock<Mutex<Throttle>> = OnceLock::new();
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Failures {
+    pub count: u32,
+    pub last: u128,
+    pub blocked_until: u128
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    Username(String),
+    Ip(IpAddr),
+    // password protected share links, visitors have no username
+    ShareLink(u128)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Blocked {
+    pub key: Key,
+    pub failures: u32,
+    pub blocked_until: u128
+}
+
+// failed login counters per username and per client ip
+// for usernames every failure past the free attempts doubles the wait before the next try is accepted,
+// and reaching the threshold locks the key out for the whole lockout period
+#[derive(Default)]
+pub struct Throttle {
+    failures: HashMap<Key, Failures>
+}
+impl Throttle {
+    // api key logins come without a username and are only tracked by ip
+    fn keys(username: &str, ip: Option<IpAddr>) -> Vec<Key> {
+        let mut result = vec![];
+        if !username.is_empty() {
+            result.push(Key::Username(username.to_string()));
+        }
+        if let Some(ip) = ip {
+            result.push(Key::Ip(ip));
+        }
+        result
+    }
+
+    // ips only ever get the full lockout, many people can sit behind one address and shouldn't slow each other down
+    fn delay(key: &Key, count: u32) -> u128 {
+        let config = config::get();
+        let (threshold, backoff) = match key {
+            Key::Username(_) | Key::ShareLink(_) => (config.login_lockout_threshold, true),
+            Key::Ip(_) => (config.login_ip_lockout_threshold, false)
+        };
+
+        if count >= threshold {
+            config.login_lockout as u128
+        } else if !backoff || count < config.login_free_attempts {
+            0
+        } else {
+            let exponent = (count - config.login_free_attempts).min(32);
+            (config.login_backoff_base << exponent).min(config.login_max_backoff) as u128
+        }
+    }
+
+    // seconds until the next attempt is allowed, if any of the keys is still blocked
+    pub fn check(&self, username: &str, ip: Option<IpAddr>, now: u128) -> Option<u128> {
+        self.check_keys(&Throttle::keys(username, ip), now)
+    }
+
+    pub fn check_keys(&self, keys: &[Key], now: u128) -> Option<u128> {
+        keys.iter()
+            .filter_map(|k| self.failures.get(k))
+            .map(|f| f.blocked_until.saturating_sub(now))
+            .filter(|wait| *wait > 0)
+            .max()
+    }
+
+    pub fn fail(&mut self, username: &str, ip: Option<IpAddr>, now: u128) {
+        self.fail_keys(Throttle::keys(username, ip), now);
+    }
+
+    pub fn fail_keys(&mut self, keys: Vec<Key>, now: u128) {
+        for key in keys {
+            let failures = self.failures.entry(key.clone()).or_default();
+            failures.count += 1;
+            failures.last = now;
+            failures.blocked_until = now + Throttle::delay(&key, failures.count);
+        }
+
+        if self.failures.len() > PRUNE_ABOVE {
+            let forget = config::get().login_lockout as u128 * FORGET_AFTER_LOCKOUTS;
+            self.failures.retain(|_, f| now.saturating_sub(f.last) < forget || f.blocked_until > now);
+        }
+    }
+
+    // only the username is cleared, one valid account shouldn't reset the counter of an ip guessing others
+    pub fn succeed(&mut self, username: &str) {
+        self.failures.remove(&Key::Username(username.to_string()));
+    }
+
+    pub fn blocked(&self, now: u128) -> Vec<Blocked> {
+        let mut result = self.failures.iter()
+            .filter(|(_, f)| f.blocked_until > now)
+            .map(|(k, f)| Blocked { key: k.clone(), failures: f.count, blocked_until: f.blocked_until })
+            .collect::<Vec<Blocked>>();
+        result.sort_by_key(|b| b.blocked_until);
+        result
+    }
+
+    pub fn unblock(&mut self, key: &Key) -> bool {
+        self.failures.remove(key).is_some()
+    }
+}
+
+pub fn get() -> &'static Mutex<Throttle> {
+    THROTTLE.get_or_init(|| Mutex::new(Throttle::default()))
+}
+
+// #region api calls
+#[post("/", data="<login>")]
+pub fn blocked(db: &State<Arc<RwLock<Database>>>, login: LoginInformation) -> String {
+    let result = login.login_admin(db);
+    match result {
+        LoginResult::Success(_) => utils::parse_response(Ok(get().lock().unwrap().blocked(utils::get_time()))),
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/username/<username>", data="<login>")]
+pub fn unblock_username(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, username: String) -> String {
+    let result = login.login_admin(db);
+    match result {
+        LoginResult::Success(_) => {
+            let key = Key::Username(utils::decode_uri(username));
+            utils::parse_response(Ok(get().lock().unwrap().unblock(&key)))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/share_link/<share_id>", data="<login>")]
+pub fn unblock_share_link(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, share_id: u128) -> String {
+    let result = login.login_admin(db);
+    match result {
+        LoginResult::Success(_) => utils::parse_response(Ok(get().lock().unwrap().unblock(&Key::ShareLink(share_id)))),
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/ip/<ip>", data="<login>")]
+pub fn unblock_ip(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, ip: IpAddr) -> String {
+    let result = login.login_admin(db);
+    match result {
+        LoginResult::Success(_) => utils::parse_response(Ok(get().lock().unwrap().unblock(&Key::Ip(ip)))),
+        _ => utils::parse_response(Err(result))
+    }
+}
+// #endregion
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use crate::config;
+
+    use super::{Key, Throttle};
+
+    const IP: Option<IpAddr> = Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+
+    #[test]
+    fn username_backoff_doubles_then_locks_out() {
+        let config = config::init_temp();
+        let mut throttle = Throttle::default();
+        let now = 1000;
+
+        for _ in 0..config.login_free_attempts - 1 {
+            throttle.fail("alice", None, now);
+            assert_eq!(throttle.check("alice", None, now), None);
+        }
+        // the last free attempt is used up, every failure from here on doubles the wait
+        throttle.fail("alice", None, now);
+        assert_eq!(throttle.check("alice", None, now), Some(config.login_backoff_base as u128));
+        assert_eq!(throttle.check("alice", None, now + config.login_backoff_base as u128), None);
+        throttle.fail("alice", None, now);
+        assert_eq!(throttle.check("alice", None, now), Some(config.login_backoff_base as u128 * 2));
+        // other usernames aren't affected
+        assert_eq!(throttle.check("bob", None, now), None);
+
+        for _ in config.login_free_attempts + 1..config.login_lockout_threshold {
+            throttle.fail("alice", None, now);
+        }
+        let lockout = config.login_lockout as u128;
+        assert_eq!(throttle.check("alice", None, now), Some(lockout));
+        assert_eq!(throttle.blocked(now).len(), 1);
+        // the lockout runs out on its own
+        assert_eq!(throttle.check("alice", None, now + lockout - 1), Some(1));
+        assert_eq!(throttle.check("alice", None, now + lockout), None);
+        assert!(throttle.blocked(now + lockout).is_empty());
+
+        throttle.succeed("alice");
+        assert_eq!(throttle.check("alice", None, now), None);
+    }
+
+    #[test]
+    fn ips_are_only_locked_out() {
+        let config = config::init_temp();
+        let mut throttle = Throttle::default();
+        let now = 1000;
+
+        // spread over usernames so none of them gets a backoff of its own, the ip itself has none before the threshold
+        for i in 0..config.login_ip_lockout_threshold - 1 {
+            throttle.fail(&format!("user{i}"), IP, now);
+        }
+        assert_eq!(throttle.check("someone", IP, now), None);
+        // empty usernames (api keys) count against the ip only
+        throttle.fail("", IP, now);
+        assert_eq!(throttle.check("someone", IP, now), Some(config.login_lockout as u128));
+        assert_eq!(throttle.check("someone", None, now), None);
+
+        // a successful login doesn't clear the ip
+        throttle.succeed("someone");
+        assert_eq!(throttle.check("", IP, now), Some(config.login_lockout as u128));
+        assert_eq!(throttle.check("", IP, now + config.login_lockout as u128), None);
+
+        assert!(throttle.unblock(&Key::Ip(IP.unwrap())));
+        assert!(!throttle.unblock(&Key::Ip(IP.unwrap())));
+    }
+
+    #[test]
+    fn share_links_back_off_whatever_the_ip() {
+        let config = config::init_temp();
+        let mut throttle = Throttle::default();
+        let now = 1000;
+
+        // every guess from a different address
+        for i in 0..config.login_free_attempts {
+            throttle.fail_keys(vec![Key::ShareLink(5), Key::Ip(IpAddr::V4(Ipv4Addr::new(10, 0, 1, i as u8)))], now);
+        }
+        assert_eq!(throttle.check_keys(&[Key::ShareLink(5)], now), Some(config.login_backoff_base as u128));
+        assert_eq!(throttle.check_keys(&[Key::ShareLink(6)], now), None);
+        assert!(throttle.unblock(&Key::ShareLink(5)));
+    }
+}
@@ -0,0 +1,335 @@
+use std::{collections::HashMap, path::PathBuf, sync::{Arc, Mutex, OnceLock, RwLock}};
+
+use rocket::State;
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::{access::Target, activity::Activity, config, database::Database, login_info::{LoginInformation, LoginResult}, team::Permissions, utils};
+
+pub const ISSUER: &str = "Athena";
+pub const DIGITS: usize = 6;
+pub const STEP: u64 = 30;
+// steps either side of the current one that are still accepted, for clocks that drift a little
+pub const SKEW: i64 = 1;
+pub const RECOVERY_CODES: usize = 10;
+pub const RECOVERY_CODE_LENGTH: usize = 12;
+pub const SESSION_TOKEN_LENGTH: usize = 32;
+
+static SESSIONS: OnceLock<Mutex<Sessions>> = OnceLock::new();
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum SecondFactorError {
+    NotTeamAdmin,
+    AlreadyEnabled,
+    NotEnrolled,
+    CodeWrong,
+    UserNoExist
+}
+
+// handed out once when enrolling, for the authenticator app
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Enrollment {
+    pub secret: String,
+    pub uri: String
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SecondFactor {
+    // base32, as shown to the user
+    pub secret: String,
+    // false until the first code is confirmed, so a half finished enrollment can't lock anyone out
+    pub enabled: bool,
+    // hashes, each one works once
+    pub recovery_codes: Vec<String>,
+    // the newest step a code was accepted for, older and equal ones are rejected so codes can't be replayed
+    pub last_step: u64
+}
+impl SecondFactor {
+    pub fn serialize(db: &Database) -> (PathBuf, String) {
+        (config::get().data_path("second_factors.json"), serde_json::to_string_pretty(&db.second_factors).unwrap())
+    }
+
+    pub fn load() -> HashMap<u128, SecondFactor> {
+        utils::load_or_default(config::get().data_path("second_factors.json"))
+    }
+
+    pub fn new() -> SecondFactor {
+        SecondFactor {
+            secret: Secret::generate_secret().to_encoded().to_string(),
+            enabled: false,
+            recovery_codes: vec![],
+            last_step: 0
+        }
+    }
+
+    fn totp(&self, username: &str) -> TOTP {
+        let secret = Secret::Encoded(self.secret.clone()).to_bytes().unwrap_or_default();
+        TOTP::new_unchecked(Algorithm::SHA1, DIGITS, 0, STEP, secret, Some(ISSUER.to_string()), username.replace(':', "_"))
+    }
+
+    pub fn enrollment(&self, username: &str) -> Enrollment {
+        Enrollment {
+            secret: self.secret.clone(),
+            uri: self.totp(username).get_url()
+        }
+    }
+
+    // returns the plain codes, only their hashes are kept
+    pub fn generate_recovery_codes(&mut self) -> Vec<String> {
+        let codes = (0..RECOVERY_CODES).map(|_| utils::generate_token(RECOVERY_CODE_LENGTH)).collect::<Vec<String>>();
+        self.recovery_codes = codes.iter().map(|c| utils::hash_token(c)).collect();
+        codes
+    }
+
+    // accepts either a current code or an unused recovery code, the caller saves on success
+    pub fn verify(&mut self, username: &str, code: &str, now: u64) -> bool {
+        let code = code.trim();
+        let totp = self.totp(username);
+        let current = (now / STEP) as i64;
+        for step in (current - SKEW..=current + SKEW).filter(|s| *s > self.last_step as i64) {
+            if totp.check(code, step as u64 * STEP) {
+                self.last_step = step as u64;
+                return true;
+            }
+        }
+
+        let hash = utils::hash_token(code);
+        match self.recovery_codes.iter().position(|c| *c == hash) {
+            Some(i) => {
+                self.recovery_codes.remove(i);
+                true
+            },
+            None => false
+        }
+    }
+
+    // 2fa is offered to anyone who administers a team
+    pub fn eligible(db: &Database, user_id: u128) -> bool {
+        db.index.user_teams.get(&user_id).is_some_and(|teams| {
+            teams.iter().any(|t| db.teams.get(t).and_then(|t| t.members.get(&user_id)) == Some(&Permissions::Admin))
+        })
+    }
+}
+
+// handed out by /2fa/session
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Session {
+    pub token: String,
+    pub expires_at: u128
+}
+
+// open sessions by token hash, kept in memory only so a restart asks everyone for a code again
+#[derive(Default)]
+pub struct Sessions {
+    tokens: HashMap<String, (u128, u128)>
+}
+impl Sessions {
+    pub fn open(&mut self, user_id: u128, now: u128, lifetime: u64) -> Session {
+        self.tokens.retain(|_, (_, expires_at)| *expires_at > now);
+        let token = utils::generate_token(SESSION_TOKEN_LENGTH);
+        let expires_at = now + lifetime as u128;
+        self.tokens.insert(utils::hash_token(&token), (user_id, expires_at));
+        Session { token, expires_at }
+    }
+
+    pub fn check(&self, token: &str, user_id: u128, now: u128) -> bool {
+        self.tokens.get(&utils::hash_token(token)).is_some_and(|(u, expires_at)| *u == user_id && *expires_at > now)
+    }
+
+    // when 2fa is turned off or reset, so old sessions don't carry over into a new enrollment
+    pub fn close_all(&mut self, user_id: u128) {
+        self.tokens.retain(|_, (u, _)| *u != user_id);
+    }
+}
+
+pub fn sessions() -> &'static Mutex<Sessions> {
+    SESSIONS.get_or_init(|| Mutex::new(Sessions::default()))
+}
+
+// #region api calls
+// starts over with a new secret if an earlier enrollment was never confirmed
+#[post("/", data="<login>")]
+pub fn enroll(db: &State<Arc<RwLock<Database>>>, login: LoginInformation) -> String {
+    if login.api_key.is_some() {
+        return utils::parse_response(Err(LoginResult::KeyNotAllowed));
+    }
+    let result = login.login(db);
+    match result {
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            if db.second_factors.get(&user_id).is_some_and(|f| f.enabled) {
+                return utils::parse_response(Err(SecondFactorError::AlreadyEnabled));
+            }
+            if !SecondFactor::eligible(&db, user_id) {
+                return utils::parse_response(Err(SecondFactorError::NotTeamAdmin));
+            }
+            let factor = SecondFactor::new();
+            let enrollment = factor.enrollment(&login.username);
+            db.second_factors.insert(user_id, factor);
+            db.save();
+            utils::parse_response(Ok(enrollment))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+// turns 2fa on with the first code from the app, answers with the recovery codes
+#[post("/<code>", data="<login>")]
+pub fn confirm(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, code: String) -> String {
+    if login.api_key.is_some() {
+        return utils::parse_response(Err(LoginResult::KeyNotAllowed));
+    }
+    let result = login.login(db);
+    match result {
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            let factor = match db.second_factors.get_mut(&user_id) {
+                Some(f) if f.enabled => return utils::parse_response(Err(SecondFactorError::AlreadyEnabled)),
+                Some(f) => f,
+                None => return utils::parse_response(Err(SecondFactorError::NotEnrolled))
+            };
+            if !factor.verify(&login.username, &code, utils::get_time() as u64) {
+                return utils::parse_response(Err(SecondFactorError::CodeWrong));
+            }
+            factor.enabled = true;
+            let codes = factor.generate_recovery_codes();
+            Activity::record(&mut db, user_id, None, Target::User(user_id), "second_factor_enabled", None::<()>, None::<()>);
+            db.save();
+            utils::parse_response(Ok(codes))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+// the only route that takes an otp code, the session it answers with stands in for the code on every other request
+#[post("/", data="<login>")]
+pub fn session(db: &State<Arc<RwLock<Database>>>, login: LoginInformation) -> String {
+    if login.api_key.is_some() {
+        return utils::parse_response(Err(LoginResult::KeyNotAllowed));
+    }
+    let result = login.login_with_code(db);
+    match result {
+        LoginResult::Success(user_id) => {
+            if !db.read().unwrap().second_factors.get(&user_id).is_some_and(|f| f.enabled) {
+                return utils::parse_response(Err(SecondFactorError::NotEnrolled));
+            }
+            let session = sessions().lock().unwrap().open(user_id, utils::get_time(), config::get().second_factor_session);
+            utils::parse_response(Ok(session))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+// replaces all recovery codes, the login needs a session like any other while 2fa is on
+#[post("/", data="<login>")]
+pub fn recovery_codes(db: &State<Arc<RwLock<Database>>>, login: LoginInformation) -> String {
+    if login.api_key.is_some() {
+        return utils::parse_response(Err(LoginResult::KeyNotAllowed));
+    }
+    let result = login.login(db);
+    match result {
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            let codes = match db.second_factors.get_mut(&user_id) {
+                Some(f) if f.enabled => f.generate_recovery_codes(),
+                _ => return utils::parse_response(Err(SecondFactorError::NotEnrolled))
+            };
+            Activity::record(&mut db, user_id, None, Target::User(user_id), "recovery_codes_replaced", None::<()>, None::<()>);
+            db.save();
+            utils::parse_response(Ok(codes))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/", data="<login>")]
+pub fn disable(db: &State<Arc<RwLock<Database>>>, login: LoginInformation) -> String {
+    if login.api_key.is_some() {
+        return utils::parse_response(Err(LoginResult::KeyNotAllowed));
+    }
+    let result = login.login(db);
+    match result {
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            if db.second_factors.remove(&user_id).is_none() {
+                return utils::parse_response(Err(SecondFactorError::NotEnrolled));
+            }
+            sessions().lock().unwrap().close_all(user_id);
+            Activity::record(&mut db, user_id, None, Target::User(user_id), "second_factor_disabled", None::<()>, None::<()>);
+            db.save();
+            utils::parse_response(Ok("success"))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+// for users who lost both their device and their recovery codes
+#[post("/<user_id>", data="<login>")]
+pub fn reset(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, user_id: u128) -> String {
+    let result = login.login_admin(db);
+    match result {
+        LoginResult::Success(actor) => {
+            let mut db = db.write().unwrap();
+            if !db.users.contains_key(&user_id) {
+                return utils::parse_response(Err(SecondFactorError::UserNoExist));
+            }
+            let removed = db.second_factors.remove(&user_id).is_some();
+            sessions().lock().unwrap().close_all(user_id);
+            if removed {
+                Activity::record(&mut db, actor, None, Target::User(user_id), "second_factor_reset", None::<()>, None::<()>);
+            }
+            db.save();
+            utils::parse_response(Ok(removed))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+// #endregion
+
+#[cfg(test)]
+mod tests {
+    use super::{SecondFactor, Sessions, STEP};
+
+    #[test]
+    fn codes_are_accepted_once() {
+        let username = "alice".to_string();
+        let mut factor = SecondFactor::new();
+        let now = 1_700_000_000;
+        let code = factor.totp(&username).generate(now);
+
+        assert!(factor.verify(&username, &code, now));
+        assert!(!factor.verify(&username, &code, now));
+        // the next step's code still works, and the one before it is too old now
+        assert!(factor.verify(&username, &factor.totp(&username).generate(now + STEP), now));
+        assert!(!factor.verify(&username, &factor.totp(&username).generate(now - STEP), now));
+    }
+
+    #[test]
+    fn recovery_codes_are_single_use() {
+        let username = "alice".to_string();
+        let mut factor = SecondFactor::new();
+        let codes = factor.generate_recovery_codes();
+
+        assert!(factor.verify(&username, &codes[3], 0));
+        assert!(!factor.verify(&username, &codes[3], 0));
+        assert_eq!(factor.recovery_codes.len(), codes.len() - 1);
+        assert!(factor.enrollment(&username).uri.starts_with("otpauth://totp/Athena:alice?secret="));
+    }
+
+    #[test]
+    fn sessions_belong_to_one_user_and_expire() {
+        let mut sessions = Sessions::default();
+        let session = sessions.open(1, 1000, 60);
+        assert_eq!(session.expires_at, 1060);
+
+        assert!(sessions.check(&session.token, 1, 1059));
+        assert!(!sessions.check(&session.token, 2, 1059));
+        assert!(!sessions.check(&session.token, 1, 1060));
+        assert!(!sessions.check("made up", 1, 1000));
+
+        let other = sessions.open(2, 1000, 60);
+        sessions.close_all(1);
+        assert!(!sessions.check(&session.token, 1, 1000));
+        assert!(sessions.check(&other.token, 2, 1000));
+    }
+}
@@ -0,0 +1,357 @@
+use std::{collections::HashMap, path::PathBuf, sync::{Arc, RwLock}, thread, time::Duration};
+
+use rocket::State;
+use serde::{Deserialize, Serialize};
+
+use crate::{access::{self, Access, Target}, activity::Activity, config, database::Database, events::{self, Change}, group::Group, login_info::{LoginInformation, LoginResult}, project::Project, search::SearchIndex, share::ShareLink, task::Task, team::Permissions, utils, webhook::Webhook};
+
+pub const TRASH_ID_MAX: u128 = 4294967296u128; // 16^8, 2^32
+
+// how often expired entries are looked for, at most
+pub const PURGE_INTERVAL: u64 = 3600;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum TrashError {
+    EntryNoExist,
+    // the project or group it was in is gone, restore that first
+    ParentNoExist,
+    // something new got its id in the meantime
+    IdTaken
+}
+
+// a deleted project, group or task together with everything that was deleted along with it
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Trashed {
+    pub entity: Target,
+    pub project_id: u128,
+    // the project or group it sat in and where, None for projects
+    pub parent: Option<u128>,
+    pub position: usize,
+
+    pub deleted_by: u128,
+    pub deleted_at: u128,
+
+    pub project: Option<Project>,
+    // in board order
+    pub groups: Vec<(u128, Group)>,
+    pub tasks: Vec<(u128, Task)>,
+    pub share_links: HashMap<u128, ShareLink>,
+    pub webhooks: HashMap<u128, Webhook>
+}
+
+// what listings show, without the webhook secrets and link hashes
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TrashSummary {
+    pub id: u128,
+    pub entity: Target,
+    pub project_id: u128,
+    pub name: String,
+    pub deleted_by: u128,
+    pub deleted_at: u128,
+    pub purge_at: Option<u128>
+}
+
+pub struct Trash;
+impl Trash {
+    pub fn serialize(db: &Database) -> (PathBuf, String) {
+        (config::get().data_path("trash.json"), serde_json::to_string_pretty(&db.trash).unwrap())
+    }
+
+    pub fn load() -> HashMap<u128, Trashed> {
+        utils::load_or_default(config::get().data_path("trash.json"))
+    }
+
+    // copies the entity and its contents into the trash, the caller removes the live data afterwards
+    pub fn put(db: &mut Database, actor: u128, entity: Target) -> Option<u128> {
+        let mut entry = Trashed {
+            entity,
+            project_id: entity.project(db)?,
+            parent: None,
+            position: 0,
+            deleted_by: actor,
+            deleted_at: utils::get_time(),
+            project: None,
+            groups: vec![],
+            tasks: vec![],
+            share_links: HashMap::new(),
+            webhooks: HashMap::new()
+        };
+        let group_ids = match entity {
+            Target::Global | Target::User(_) => return None,
+            Target::Project(project_id) => {
+                let project = db.projects.get(&project_id)?;
+                entry.project = Some(project.clone());
+                entry.share_links = db.share_links.iter().filter(|(_, l)| l.project_id == project_id).map(|(i, l)| (*i, l.clone())).collect();
+                entry.webhooks = db.webhooks.iter().filter(|(_, w)| w.project_id == project_id).map(|(i, w)| (*i, w.clone())).collect();
+                project.groups.clone()
+            },
+            Target::Group(group_id) => {
+                entry.parent = Some(entry.project_id);
+                entry.position = db.projects.get(&entry.project_id)?.groups.iter().position(|g| *g == group_id)?;
+                vec![group_id]
+            },
+            Target::Task(task_id) => {
+                let group_id = Group::parent_of_task(db, task_id)?;
+                entry.parent = Some(group_id);
+                entry.position = db.groups.get(&group_id)?.tasks.iter().position(|t| *t == task_id)?;
+                entry.tasks = vec![(task_id, db.tasks.get(&task_id)?.clone())];
+                vec![]
+            }
+        };
+        for g in group_ids {
+            if let Some(group) = db.groups.get(&g) {
+                entry.tasks.extend(group.tasks.iter().filter_map(|t| db.tasks.get(t).map(|task| (*t, task.clone()))));
+                entry.groups.push((g, group.clone()));
+            }
+        }
+
+        let id = utils::generate_id(db.trash.keys().copied().collect::<Vec<u128>>(), TRASH_ID_MAX);
+        db.trash.insert(id, entry);
+        Some(id)
+    }
+
+    pub fn restore(db: &mut Database, actor: u128, trash_id: u128) -> Result<(), TrashError> {
+        let entry = db.trash.get(&trash_id).ok_or(TrashError::EntryNoExist)?.clone();
+        entry.reinstate(db)?;
+        db.trash.remove(&trash_id);
+
+        let after = match entry.entity {
+            Target::Project(project_id) => serde_json::to_value(db.projects.get(&project_id)).ok(),
+            Target::Group(group_id) => serde_json::to_value(db.groups.get(&group_id)).ok(),
+            Target::Task(task_id) => serde_json::to_value(db.tasks.get(&task_id)).ok(),
+            Target::Global | Target::User(_) => None
+        };
+        Activity::record(db, actor, Some(entry.project_id), entry.entity, "restored", None::<()>, after);
+        db.save();
+
+        let change = match entry.entity {
+            Target::Project(_) => Change::ProjectRestored,
+            Target::Group(group_id) => Change::GroupRestored { group_id, position: entry.position, group: db.groups.get(&group_id).unwrap().clone() },
+            Target::Task(task_id) => Change::TaskRestored { group_id: entry.parent.unwrap(), position: entry.position, task: db.tasks.get(&task_id).unwrap().clone() },
+            Target::Global | Target::User(_) => return Ok(())
+        };
+        events::emit(db, Some(entry.project_id), change);
+        Ok(())
+    }
+
+    // drops entries deleted more than `retention` seconds before `now`, returns how many
+    pub fn purge(db: &mut Database, retention: u64, now: u128) -> usize {
+        if retention == 0 {
+            return 0;
+        }
+        let before = db.trash.len();
+        db.trash.retain(|_, e| e.deleted_at + retention as u128 > now);
+        before - db.trash.len()
+    }
+
+    pub fn schedule(db: Arc<RwLock<Database>>) {
+        let retention = config::get().trash_retention;
+        if retention == 0 {
+            return;
+        }
+
+        thread::spawn(move || loop {
+            {
+                let mut db = db.write().unwrap();
+                if Trash::purge(&mut db, retention, utils::get_time()) > 0 {
+                    db.save();
+                }
+            }
+            thread::sleep(Duration::from_secs(retention.min(PURGE_INTERVAL)));
+        });
+    }
+
+    // what a user may do with an entry: for deleted projects the copy decides, otherwise the live project
+    pub fn permissions(db: &Database, user_id: u128, entry: &Trashed) -> Permissions {
+        match &entry.project {
+            Some(p) => access::permissions_in(db, user_id, p),
+            None => access::project_permissions(db, user_id, entry.project_id)
+        }
+    }
+
+    pub fn list(db: &Database, user_id: u128) -> Vec<TrashSummary> {
+        let retention = config::get().trash_retention as u128;
+        let mut result = db.trash.iter()
+            .filter(|(_, e)| Trash::permissions(db, user_id, e).allows(Permissions::Viewer))
+            .map(|(i, e)| TrashSummary {
+                id: *i,
+                entity: e.entity,
+                project_id: e.project_id,
+                name: e.name(),
+                deleted_by: e.deleted_by,
+                deleted_at: e.deleted_at,
+                purge_at: (retention != 0).then(|| e.deleted_at + retention)
+            })
+            .collect::<Vec<TrashSummary>>();
+        result.sort_by_key(|e| std::cmp::Reverse((e.deleted_at, e.id)));
+        result
+    }
+}
+
+impl Trashed {
+    pub fn name(&self) -> String {
+        match self.entity {
+            Target::Project(_) => self.project.as_ref().map(|p| p.name.clone()),
+            Target::Group(_) => self.groups.first().map(|(_, g)| g.name.clone()),
+            Target::Task(_) => self.tasks.first().map(|(_, t)| t.title.clone()),
+            Target::Global | Target::User(_) => None
+        }.unwrap_or_default()
+    }
+
+    // puts everything back where it was, positions past the end of a list that has shrunk since land at the end
+    pub fn reinstate(&self, db: &mut Database) -> Result<(), TrashError> {
+        let taken = self.groups.iter().any(|(g, _)| db.groups.contains_key(g))
+            || self.tasks.iter().any(|(t, _)| db.tasks.contains_key(t))
+            || self.share_links.keys().any(|l| db.share_links.contains_key(l))
+            || self.webhooks.keys().any(|w| db.webhooks.contains_key(w))
+            || (self.project.is_some() && db.projects.contains_key(&self.project_id));
+        if taken {
+            return Err(TrashError::IdTaken);
+        }
+
+        match self.entity {
+            Target::Project(project_id) => {
+                db.projects.insert(project_id, self.project.clone().unwrap());
+                db.share_links.extend(self.share_links.clone());
+                db.webhooks.extend(self.webhooks.clone());
+            },
+            Target::Group(group_id) => {
+                let groups = &mut db.projects.get_mut(&self.project_id).ok_or(TrashError::ParentNoExist)?.groups;
+                groups.insert(self.position.min(groups.len()), group_id);
+            },
+            Target::Task(task_id) => {
+                let group_id = self.parent.unwrap_or_default();
+                let tasks = &mut db.groups.get_mut(&group_id).ok_or(TrashError::ParentNoExist)?.tasks;
+                tasks.insert(self.position.min(tasks.len()), task_id);
+                db.index.task_group.insert(task_id, group_id);
+            },
+            Target::Global | Target::User(_) => {}
+        }
+
+        for (g, group) in &self.groups {
+            db.groups.insert(*g, group.clone());
+            db.index.group_project.insert(*g, self.project_id);
+            for t in &group.tasks {
+                db.index.task_group.insert(*t, *g);
+            }
+        }
+        db.tasks.extend(self.tasks.clone());
+
+        let entities = self.groups.iter().map(|(g, _)| Target::Group(*g))
+            .chain(self.tasks.iter().map(|(t, _)| Target::Task(*t)))
+            .chain(self.project.is_some().then_some(Target::Project(self.project_id)));
+        for e in entities.collect::<Vec<Target>>() {
+            SearchIndex::refresh(db, e);
+        }
+        Ok(())
+    }
+}
+
+// #region api calls
+// everything in the trash the caller can see, newest first
+#[post("/", data="<login>")]
+pub fn list(db: &State<Arc<RwLock<Database>>>, login: LoginInformation) -> String {
+    let result = login.authorize(db, Access::Read, Target::Global);
+    match result {
+        LoginResult::Success(user_id) => {
+            let db = db.read().unwrap();
+            utils::parse_response(Ok(Trash::list(&db, user_id)))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+// projects need the same permissions to restore as to delete, groups and tasks editor access to their project
+#[post("/<trash_id>", data="<login>")]
+pub fn restore(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, trash_id: u128) -> String {
+    let result = login.authorize(db, Access::Write, Target::Global);
+    match result {
+        LoginResult::Success(user_id) => {
+            let mut db = db.write().unwrap();
+            let required = match db.trash.get(&trash_id) {
+                Some(e) if e.project.is_some() => Access::Admin.required(),
+                Some(_) => Access::Write.required(),
+                None => return utils::parse_response(Err(TrashError::EntryNoExist))
+            };
+            if !Trash::permissions(&db, user_id, db.trash.get(&trash_id).unwrap()).allows(required) {
+                return utils::parse_response(Err(LoginResult::PermissionDenied));
+            }
+            let entry = db.trash.get(&trash_id).unwrap();
+            if entry.project.is_none() && db.projects.get(&entry.project_id).is_some_and(|p| p.archived) {
+                return utils::parse_response(Err(LoginResult::ProjectArchived));
+            }
+            match Trash::restore(&mut db, user_id, trash_id) {
+                Ok(_) => utils::parse_response(Ok("success")),
+                Err(e) => utils::parse_response(Err(e))
+            }
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+// #endregion
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{access::Target, database::Database, group::Group, indices::Index, project::{Ownership, Project}, task::{Species, Task}};
+
+    use super::Trash;
+
+    fn board() -> Database {
+        let mut db = Database::default();
+        db.projects.insert(1, Project { name: "project".to_string(), owner: Ownership::User(1), groups: vec![10, 11], members: HashMap::new(), archived: false, offered_to: None, created_at: 0, created_by: None, updated_at: 0, updated_by: None });
+        db.groups.insert(10, Group { name: "todo".to_string(), tasks: vec![100, 101, 102], archived: false, created_at: 0, created_by: None, updated_at: 0, updated_by: None });
+        db.groups.insert(11, Group { name: "done".to_string(), tasks: vec![103], archived: false, created_at: 0, created_by: None, updated_at: 0, updated_by: None });
+        for t in 100..104 {
+            db.tasks.insert(t, Task { id: t, title: format!("task {t}"), description: String::new(), species: Species::Task(false), assigned: vec![], labels: vec![], due: None, created_at: 0, created_by: None, updated_at: 0, updated_by: None });
+        }
+        db.index = Index::build(&db);
+        db
+    }
+
+    #[test]
+    fn restores_in_place() {
+        let mut db = board();
+
+        let task = Trash::put(&mut db, 1, Target::Task(101)).unwrap();
+        db.groups.get_mut(&10).unwrap().tasks.retain(|t| *t != 101);
+        db.tasks.remove(&101);
+        db.index.task_group.remove(&101);
+
+        let group = Trash::put(&mut db, 1, Target::Group(10)).unwrap();
+        assert_eq!(db.trash.get(&group).unwrap().tasks.len(), 2);
+        db.projects.get_mut(&1).unwrap().groups.retain(|g| *g != 10);
+        for t in [100, 102] {
+            db.tasks.remove(&t);
+        }
+        db.groups.remove(&10);
+        db.reindex();
+
+        // the task's group is still gone
+        assert!(db.trash.get(&task).unwrap().clone().reinstate(&mut db).is_err());
+
+        db.trash.get(&group).unwrap().clone().reinstate(&mut db).unwrap();
+        assert_eq!(db.projects.get(&1).unwrap().groups, vec![10, 11]);
+        db.trash.get(&task).unwrap().clone().reinstate(&mut db).unwrap();
+        assert_eq!(db.groups.get(&10).unwrap().tasks, vec![100, 101, 102]);
+        assert_eq!(Target::Task(101).project(&db), Some(1));
+        assert_eq!(db.index, Index::build(&db));
+
+        // reinstating twice would duplicate everything
+        assert!(db.trash.get(&task).unwrap().clone().reinstate(&mut db).is_err());
+    }
+
+    #[test]
+    fn purges_after_retention() {
+        let mut db = board();
+        let id = Trash::put(&mut db, 1, Target::Project(1)).unwrap();
+        let deleted_at = db.trash.get(&id).unwrap().deleted_at;
+        assert_eq!(db.trash.get(&id).unwrap().tasks.len(), 4);
+
+        assert_eq!(Trash::purge(&mut db, 0, deleted_at + 100), 0);
+        assert_eq!(Trash::purge(&mut db, 100, deleted_at + 99), 0);
+        assert_eq!(Trash::purge(&mut db, 100, deleted_at + 100), 1);
+        assert!(db.trash.is_empty());
+    }
+}
@@ -1,38 +1,88 @@
-use std::{collections::HashMap, fs, str::FromStr, sync::Mutex};
+use std::{collections::HashMap, fs, path::PathBuf, str::FromStr, sync::{Arc, RwLock}};
 
 use rocket::State;
 use serde::{Deserialize, Serialize};
+use strum_macros::EnumString;
 
-use crate::{database::Database, login_info::{LoginInformation, LoginResult}, project::{self, Ownership, Project}, team::Team, utils};
+use crate::{access::{Access, Target}, activity::Activity, config, database::Database, login_info::{LoginInformation, LoginResult}, paging::{self, Key, Paging, Sort}, team::Team, utils};
 
 #[derive(Serialize, Deserialize)]
 pub struct User {
     pub id: u128,
     pub username: String,
+
+    #[serde(default)]
+    pub role: Role
 }
 impl User {
-    pub fn save(db: &Database) {
-        fs::write("data/users.json", serde_json::to_string_pretty(&db.users).unwrap()).unwrap();
+    pub fn serialize(db: &Database) -> (PathBuf, String) {
+        (config::get().data_path("users.json"), serde_json::to_string_pretty(&db.users).unwrap())
     }
 
     pub fn load() -> HashMap<u128, User> {
-        serde_json::from_str(fs::read_to_string("data/users.json").unwrap().as_str()).unwrap()
+        serde_json::from_str(fs::read_to_string(config::get().data_path("users.json")).unwrap().as_str()).unwrap()
     }
 
-    pub fn fetch_teams(db: &Database, user_id: u128) -> Vec<Team> {
-        db.teams.iter().filter(|(_, t)| t.members.contains_key(&user_id)).map(|(_, i)| i.clone()).collect::<Vec<Team>>()
+    pub fn fetch_teams(db: &Database, user_id: u128) -> Vec<(u128, Team)> {
+        match db.index.user_teams.get(&user_id) {
+            Some(teams) => teams.iter().filter_map(|i| db.teams.get(i).map(|t| (*i, t.clone()))).collect::<Vec<(u128, Team)>>(),
+            None => vec![]
+        }
     }
 }
 
 
+// server wide, unrelated to the per team Permissions
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug, EnumString)]
+pub enum Role {
+    #[default]
+    #[strum(ascii_case_insensitive)]
+    Member,
+    #[strum(ascii_case_insensitive)]
+    Admin
+}
+
 // #region api calls
-#[post("/", data="<login>")]
-pub fn fetch_teams(db: &State<Mutex<Database>>, login: LoginInformation) -> String {
-    let mut db = db.lock().unwrap();
-    let result = login.login(&mut db);
+// teams keep no timestamps, sorting them by created or updated falls back to their ids
+#[post("/?<paging..>", data="<login>")]
+pub fn fetch_teams(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, paging: Paging) -> String {
+    let result = login.authorize(db, Access::Read, Target::Global);
     match result {
         LoginResult::Success(user_id) => {
-            utils::parse_response(Ok(User::fetch_teams(&db, user_id)))
+            let db = db.read().unwrap();
+            let teams = User::fetch_teams(&db, user_id);
+            match paging.apply(teams, |sort, _, t| match sort {
+                Sort::Name => paging::name_key(&t.name),
+                _ => Key::Time(0)
+            }) {
+                Ok(page) => utils::parse_response(Ok(page)),
+                Err(e) => utils::parse_response(Err(e))
+            }
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/<user_id>/<raw_role>", data="<login>")]
+pub fn set_role(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, user_id: u128, raw_role: String) -> String {
+    let result = login.login_admin(db);
+    match result {
+        LoginResult::Success(actor) => {
+            let mut db = db.write().unwrap();
+            let role = match Role::from_str(&raw_role) {
+                Ok(r) => r,
+                Err(_) => return utils::parse_response(Err(""))
+            };
+            match db.users.get_mut(&user_id) {
+                Some(u) => {
+                    let before = u.role;
+                    u.role = role;
+                    Activity::record(&mut db, actor, None, Target::User(user_id), "role_changed", Some(before), Some(role));
+                    db.save();
+                    utils::parse_response(Ok("success"))
+                },
+                None => utils::parse_response(Err(""))
+            }
         },
         _ => utils::parse_response(Err(result))
     }
@@ -1,7 +1,9 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io, path::Path, sync::atomic::{AtomicU64, Ordering}, time::{SystemTime, UNIX_EPOCH}};
 
 use rand::prelude::*;
+use sha2::{Digest, Sha256};
 
+#[allow(dead_code)]
 const ADJECTIVES: &str = "abandoned
 able
 absolute
@@ -1331,6 +1333,7 @@ writhing
 wrong
 wry";
 
+#[allow(dead_code)]
 const NOUNS: &str = "ATM
 CD
 SUV
@@ -8141,18 +8144,21 @@ pub fn get_time() -> u128 {
         .as_secs() as u128
 }
 
+// not called anywhere at the moment, kept for handing out default names
+#[allow(dead_code)]
 pub fn generate_name(rng: &mut ThreadRng) -> String {
     format!(
         "{}{}",
-        ADJECTIVES.to_string().split("\n").map(|x| x.to_string()).collect::<Vec<String>>()[
-            rng.gen_range(0..ADJECTIVES.to_string().split("\n").count())
+        ADJECTIVES.split("\n").map(|x| x.to_string()).collect::<Vec<String>>()[
+            rng.gen_range(0..ADJECTIVES.split("\n").count())
         ],
-        NOUNS.to_string().split("\n").map(|x| x.to_string()).collect::<Vec<String>>()[
-            rng.gen_range(0..NOUNS.to_string().split("\n").count())
+        NOUNS.split("\n").map(|x| x.to_string()).collect::<Vec<String>>()[
+            rng.gen_range(0..NOUNS.split("\n").count())
         ]
     )
 }
 
+#[allow(dead_code)]
 pub fn parse_response_to_string(data: Result<String, String>) -> String {
     match data {
         Ok(d) => format!(r#"{{"type":"success","data":{d}}}"#),
@@ -8162,8 +8168,8 @@ pub fn parse_response_to_string(data: Result<String, String>) -> String {
 
 pub fn parse_response<T: serde::Serialize>(data: Result<T, T>) -> String {
     match data {
-        Ok(d) => format!(r#"{{"type":"success","data":"{}"}}"#, urlencoding::encode(serde_json::to_string(&d).unwrap().as_str()).to_string()),
-        Err(e) => format!(r#"{{"type":"fail","error":"{}"}}"#, urlencoding::encode(serde_json::to_string(&e).unwrap().as_str()).to_string())
+        Ok(d) => format!(r#"{{"type":"success","data":"{}"}}"#, urlencoding::encode(serde_json::to_string(&d).unwrap().as_str())),
+        Err(e) => format!(r#"{{"type":"fail","error":"{}"}}"#, urlencoding::encode(serde_json::to_string(&e).unwrap().as_str()))
     }
 }
 
@@ -8171,6 +8177,7 @@ pub fn decode_uri(i: String) -> String {
     urlencoding::decode(&i).unwrap().to_string()
 }
 
+#[allow(dead_code)]
 pub fn encode_uri(i: String) -> String {
     urlencoding::encode(&i).to_string()
 }
@@ -8190,3 +8197,36 @@ pub fn generate_id(indices: Vec<u128>, maximum: u128) -> u128 {
     }
     fallback
 }
+
+// for data files added after the original five, so existing data dirs without them still load
+pub fn load_or_default<T: serde::de::DeserializeOwned + Default, P: AsRef<Path>>(path: P) -> T {
+    match fs::read_to_string(path) {
+        Ok(s) => serde_json::from_str(s.as_str()).unwrap(),
+        Err(_) => T::default()
+    }
+}
+
+pub fn write_atomic<P: AsRef<Path>>(path: P, contents: String) -> io::Result<()> {
+    // write next to the target then rename over it, so a crash never leaves a half written file behind
+    // the counter keeps two writers of the same file (persister and shutdown flush) off each other's temp file
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let path = path.as_ref();
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{}.tmp", COUNTER.fetch_add(1, Ordering::Relaxed)));
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path)
+}
+
+// url safe random string for invites, keys and share links
+pub fn generate_token(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
+
+// for secrets we only ever compare against, like api keys and recovery codes
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,629 @@
+use std::{collections::{HashMap, VecDeque}, io, net::{IpAddr, SocketAddr, ToSocketAddrs}, path::PathBuf, sync::{mpsc::{self, RecvTimeoutError, Sender}, Arc, OnceLock, RwLock}, thread, time::Duration};
+
+use hmac::{Hmac, Mac};
+use rocket::{tokio::task, State};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+use ureq::{config::Config, http::Uri, unversioned::{resolver::{DefaultResolver, ResolvedSocketAddrs, Resolver}, transport::{DefaultConnector, NextTimeout}}};
+
+use crate::{access::{Access, Target}, activity::Activity, config, database::Database, events::{self, Change, Event}, login_info::{LoginInformation, LoginResult}, utils};
+
+pub const WEBHOOK_ID_MAX: u128 = 4294967296u128; // 16^8, 2^32
+pub const SECRET_LENGTH: usize = 32;
+pub const SIGNATURE_HEADER: &str = "X-Athena-Signature";
+pub const EVENT_HEADER: &str = "X-Athena-Event";
+// seconds the dispatcher sleeps when nothing is queued, it is woken early by new jobs
+const IDLE_WAIT: u64 = 3600;
+
+// wakes the dispatcher when jobs are queued
+static WAKE: OnceLock<Sender<()>> = OnceLock::new();
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum WebhookError {
+    WebhookNoExist,
+    UrlInvalid,
+    // resolves to an address that isn't public, and the host isn't in webhook_allowed_hosts
+    DestinationNotAllowed,
+    EventInvalid(String)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Webhook {
+    pub project_id: u128,
+    pub url: String,
+    // event types to send (the "type" field of a change), empty for all of them
+    pub events: Vec<String>,
+    // signs the payloads, receivers get it once when the webhook is registered
+    pub secret: String,
+
+    pub created_by: u128,
+    pub created_at: u128
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Delivery {
+    pub event: String,
+    pub attempt: u32,
+    pub time: u128,
+    // None if no response came back at all
+    pub status: Option<u16>,
+    pub error: Option<String>
+}
+impl Delivery {
+    pub fn succeeded(&self) -> bool {
+        self.status.is_some_and(|s| (200..300).contains(&s))
+    }
+}
+
+// one payload on its way to one webhook, queued in the database so retries survive a restart
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Job {
+    pub id: u64,
+    pub webhook_id: u128,
+    pub url: String,
+    pub secret: String,
+    pub event: String,
+    pub body: String,
+
+    pub attempt: u32,
+    // unix seconds before which it isn't tried (again)
+    pub due: u128
+}
+
+impl Webhook {
+    pub fn serialize(db: &Database) -> Vec<(PathBuf, String)> {
+        vec![
+            (config::get().data_path("webhooks.json"), serde_json::to_string_pretty(&db.webhooks).unwrap()),
+            (config::get().data_path("webhook_deliveries.json"), serde_json::to_string_pretty(&db.webhook_deliveries).unwrap()),
+            (config::get().data_path("webhook_queue.json"), serde_json::to_string_pretty(&db.webhook_queue).unwrap())
+        ]
+    }
+
+    pub fn load() -> HashMap<u128, Webhook> {
+        utils::load_or_default(config::get().data_path("webhooks.json"))
+    }
+
+    pub fn load_deliveries() -> HashMap<u128, VecDeque<Delivery>> {
+        utils::load_or_default(config::get().data_path("webhook_deliveries.json"))
+    }
+
+    pub fn load_queue() -> Vec<Job> {
+        utils::load_or_default(config::get().data_path("webhook_queue.json"))
+    }
+
+    pub fn validate(url: &str, events: &[String]) -> Result<(), WebhookError> {
+        if !(url.starts_with("http://") || url.starts_with("https://")) {
+            return Err(WebhookError::UrlInvalid);
+        }
+        match events.iter().find(|e| !events::KINDS.contains(&e.as_str())) {
+            Some(e) => Err(WebhookError::EventInvalid(e.clone())),
+            None => Ok(())
+        }
+    }
+
+    // returns the id and the signing secret, the caller checks the destination first as that means a dns lookup
+    pub fn create(db: &mut Database, project_id: u128, user_id: u128, url: String, events: Vec<String>) -> Result<(u128, String), WebhookError> {
+        Webhook::validate(&url, &events)?;
+        let id = utils::generate_id(db.webhooks.keys().copied().collect::<Vec<u128>>(), WEBHOOK_ID_MAX);
+        let secret = utils::generate_token(SECRET_LENGTH);
+        db.webhooks.insert(id, Webhook {
+            project_id,
+            url,
+            events,
+            secret: secret.clone(),
+            created_by: user_id,
+            created_at: utils::get_time()
+        });
+        // everything but the secret
+        let after = db.webhooks.get(&id).map(|w| json!({ "id": id, "url": w.url, "events": w.events }));
+        Activity::record(db, user_id, Some(project_id), Target::Project(project_id), "webhook_created", None::<()>, after);
+        db.save();
+        Ok((id, secret))
+    }
+
+    pub fn delete(db: &mut Database, actor: u128, webhook_id: u128) {
+        if let Some(w) = db.webhooks.remove(&webhook_id) {
+            db.webhook_deliveries.remove(&webhook_id);
+            let before = json!({ "id": webhook_id, "url": w.url, "events": w.events });
+            Activity::record(db, actor, Some(w.project_id), Target::Project(w.project_id), "webhook_deleted", Some(before), None::<()>);
+            db.save();
+        }
+    }
+
+    pub fn wants(&self, event: &Event) -> bool {
+        self.project_id == event.project_id && (self.events.is_empty() || self.events.contains(&event.change.kind()))
+    }
+
+    pub fn job(&self, id: u64, webhook_id: u128, event: &Event) -> Job {
+        Job {
+            id,
+            webhook_id,
+            url: self.url.clone(),
+            secret: self.secret.clone(),
+            event: event.change.kind(),
+            body: serde_json::to_string(event).unwrap(),
+            attempt: 0,
+            due: 0
+        }
+    }
+
+    // newest last, only the last `webhook_log_size` are kept
+    pub fn log(db: &mut Database, webhook_id: u128, delivery: Delivery) {
+        if !db.webhooks.contains_key(&webhook_id) {
+            return;
+        }
+        let log = db.webhook_deliveries.entry(webhook_id).or_default();
+        log.push_back(delivery);
+        while log.len() > config::get().webhook_log_size {
+            log.pop_front();
+        }
+        db.save();
+    }
+
+    // queues a job for every webhook that wants the event, called by events::emit under the same lock as the change
+    pub fn enqueue(db: &mut Database, event: &Event) {
+        let mut id = db.webhook_queue.iter().map(|j| j.id).max().unwrap_or(0);
+        let jobs = db.webhooks.iter()
+            .filter(|(_, w)| w.wants(event))
+            .map(|(i, w)| {
+                id += 1;
+                w.job(id, *i, event)
+            })
+            .collect::<Vec<Job>>();
+        if jobs.is_empty() {
+            return;
+        }
+        db.webhook_queue.extend(jobs);
+        if let Some(wake) = WAKE.get() {
+            let _ = wake.send(());
+        }
+    }
+
+    // the jobs that are due and how many seconds until the next one after them is
+    pub fn due(db: &Database, now: u128) -> (Vec<Job>, u64) {
+        let (due, later): (Vec<&Job>, Vec<&Job>) = db.webhook_queue.iter().partition(|j| j.due <= now);
+        let wait = later.iter().map(|j| (j.due - now) as u64).min().unwrap_or(IDLE_WAIT);
+        (due.into_iter().cloned().collect(), wait)
+    }
+
+    // logs an attempt and either schedules the next one or takes the job out of the queue
+    // deleted webhooks drop out of the retries as well
+    pub fn settle(db: &mut Database, job: &Job, delivery: Delivery, max_attempts: u32, backoff_base: u64) {
+        let retry = !delivery.succeeded() && job.attempt < max_attempts && db.webhooks.contains_key(&job.webhook_id);
+        Webhook::log(db, job.webhook_id, delivery);
+        if let Some(i) = db.webhook_queue.iter().position(|j| j.id == job.id) {
+            if retry {
+                db.webhook_queue[i] = Job { due: utils::get_time() + backoff(backoff_base, job.attempt) as u128, ..job.clone() };
+            } else {
+                db.webhook_queue.remove(i);
+            }
+        }
+        db.save();
+    }
+
+    // sends queued jobs as they come due, the queue is only changed once an attempt is over so a restart
+    // in the middle sends that attempt again rather than losing it
+    pub fn dispatch(db: Arc<RwLock<Database>>) {
+        let (wake, woken) = mpsc::channel::<()>();
+        let _ = WAKE.set(wake);
+
+        thread::spawn(move || {
+            let config = config::get();
+            let agent = agent(config.webhook_timeout, &config.webhook_allowed_hosts);
+            loop {
+                let (due, wait) = Webhook::due(&db.read().unwrap(), utils::get_time());
+                if due.is_empty() {
+                    match woken.recv_timeout(Duration::from_secs(wait)) {
+                        Ok(()) | Err(RecvTimeoutError::Timeout) => {},
+                        Err(RecvTimeoutError::Disconnected) => return
+                    }
+                    while woken.try_recv().is_ok() {}
+                    continue;
+                }
+
+                for mut job in due {
+                    job.attempt += 1;
+                    // no lock is held while the request is out
+                    let delivery = deliver(&agent, &job);
+                    Webhook::settle(&mut db.write().unwrap(), &job, delivery, config.webhook_max_attempts, config.webhook_backoff_base);
+                }
+            }
+        });
+    }
+}
+
+// seconds to wait after the given (1 based) attempt failed
+pub fn backoff(base: u64, attempt: u32) -> u64 {
+    base.saturating_mul(1u64 << (attempt.saturating_sub(1)).min(32))
+}
+
+// hex hmac-sha256 of the body, receivers compare it with the signature header
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body.as_bytes());
+    mac.finalize().into_bytes().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+// redirects and proxies are off, either would take the request somewhere the destination check never saw
+pub fn agent(timeout: u64, allowed_hosts: &[String]) -> ureq::Agent {
+    let config = ureq::Agent::config_builder()
+        .timeout_global(Some(Duration::from_secs(timeout)))
+        .http_status_as_error(false)
+        .max_redirects(0)
+        .proxy(None)
+        .build();
+    ureq::Agent::with_parts(config, DefaultConnector::new(), Destinations { allowed_hosts: allowed_hosts.to_vec() })
+}
+
+// checks the addresses ureq is about to connect to, so a host can't resolve to something else between check and send
+#[derive(Debug)]
+struct Destinations {
+    allowed_hosts: Vec<String>
+}
+impl Resolver for Destinations {
+    fn resolve(&self, uri: &Uri, config: &Config, timeout: NextTimeout) -> Result<ResolvedSocketAddrs, ureq::Error> {
+        let addresses = DefaultResolver::default().resolve(uri, config, timeout)?;
+        match allowed(uri.host().unwrap_or_default(), &addresses, &self.allowed_hosts) {
+            true => Ok(addresses),
+            false => Err(ureq::Error::Io(io::Error::new(io::ErrorKind::PermissionDenied, format!("{:?}", WebhookError::DestinationNotAllowed))))
+        }
+    }
+}
+
+// anything a webhook could use to reach into the network athena runs in: loopback, private, link-local,
+// shared, reserved and the like, ipv4 addresses wrapped in ipv6 ones included
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            !(v4.is_unspecified() || v4.is_loopback() || v4.is_private() || v4.is_link_local() || v4.is_broadcast()
+                || v4.is_multicast() || v4.is_documentation()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)) // shared address space
+                || (a == 192 && b == 0 && c == 0) // protocol assignments
+                || (a == 198 && (b == 18 || b == 19)) // benchmarking
+                || a >= 240)
+        },
+        IpAddr::V6(v6) => {
+            let segments = v6.segments();
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(v4));
+            }
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] { // nat64
+                return is_public(IpAddr::V4([(segments[6] >> 8) as u8, segments[6] as u8, (segments[7] >> 8) as u8, segments[7] as u8].into()));
+            }
+            !(v6.is_unspecified() || v6.is_loopback() || v6.is_multicast()
+                || segments[..6] == [0, 0, 0, 0, 0, 0] // ipv4 compatible
+                || (segments[0] & 0xfe00) == 0xfc00 // unique local
+                || (segments[0] & 0xffc0) == 0xfe80 // link local
+                || (segments[0] == 0x2001 && segments[1] == 0xdb8)) // documentation
+        }
+    }
+}
+
+// every address the url's host resolves to has to be public, unless the host is allowlisted
+pub fn check_destination(url: &str, allowed_hosts: &[String]) -> Result<(), WebhookError> {
+    let uri = url.parse::<Uri>().map_err(|_| WebhookError::UrlInvalid)?;
+    let host = uri.host().ok_or(WebhookError::UrlInvalid)?;
+    let port = uri.port_u16().unwrap_or(if uri.scheme_str() == Some("https") { 443 } else { 80 });
+    let addresses = (host.trim_start_matches('[').trim_end_matches(']'), port).to_socket_addrs().map_err(|_| WebhookError::UrlInvalid)?.collect::<Vec<SocketAddr>>();
+    match allowed(host, &addresses, allowed_hosts) {
+        true => Ok(()),
+        false => Err(WebhookError::DestinationNotAllowed)
+    }
+}
+
+fn allowed(host: &str, addresses: &[SocketAddr], allowed_hosts: &[String]) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    allowed_hosts.iter().any(|h| h.eq_ignore_ascii_case(host)) || (!addresses.is_empty() && addresses.iter().all(|a| is_public(a.ip())))
+}
+
+// one attempt, no retries. the agent checks the destination again, what the host resolves to may have
+// changed since the webhook was registered
+pub fn deliver(agent: &ureq::Agent, job: &Job) -> Delivery {
+    let result = agent.post(&job.url)
+        .header("Content-Type", "application/json")
+        .header(EVENT_HEADER, &job.event)
+        .header(SIGNATURE_HEADER, &format!("sha256={}", sign(&job.secret, &job.body)))
+        .send(&job.body);
+
+    let (status, error) = match result {
+        Ok(r) => (Some(r.status().as_u16()), None),
+        Err(e) => (None, Some(e.to_string()))
+    };
+    Delivery {
+        event: job.event.clone(),
+        attempt: job.attempt,
+        time: utils::get_time(),
+        status,
+        error
+    }
+}
+
+// #region api calls
+// `events` is a comma separated list of event types, leave it out for all of them
+#[post("/<project_id>/<url>?<events>", data="<login>")]
+pub fn create(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, project_id: u128, url: String, events: Option<String>) -> String {
+    let result = login.authorize(db, Access::Admin, Target::Project(project_id));
+    match result {
+        LoginResult::Success(user_id) => {
+            let events = events.map(|e| e.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect::<Vec<String>>()).unwrap_or_default();
+            let url = utils::decode_uri(url);
+            // resolved before the lock is taken, nobody else has to wait on dns
+            if let Err(e) = Webhook::validate(&url, &events).and_then(|_| check_destination(&url, &config::get().webhook_allowed_hosts)) {
+                return utils::parse_response(Err(e));
+            }
+            let mut db = db.write().unwrap();
+            match Webhook::create(&mut db, project_id, user_id, url, events) {
+                Ok(r) => utils::parse_response(Ok(r)),
+                Err(e) => utils::parse_response(Err(e))
+            }
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+// secrets are left out
+#[post("/<project_id>", data="<login>")]
+pub fn list(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, project_id: u128) -> String {
+    let result = login.authorize(db, Access::Admin, Target::Project(project_id));
+    match result {
+        LoginResult::Success(_) => {
+            let db = db.read().unwrap();
+            let mut hooks = db.webhooks.iter()
+                .filter(|(_, w)| w.project_id == project_id)
+                .map(|(i, w)| (*i, Webhook { secret: String::new(), ..w.clone() }))
+                .collect::<Vec<(u128, Webhook)>>();
+            hooks.sort_by_key(|(i, w)| (w.created_at, *i));
+            utils::parse_response(Ok(hooks))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+fn project_of(db: &RwLock<Database>, webhook_id: u128) -> Result<u128, String> {
+    match db.read().unwrap().webhooks.get(&webhook_id) {
+        Some(w) => Ok(w.project_id),
+        None => Err(utils::parse_response(Err(WebhookError::WebhookNoExist)))
+    }
+}
+
+#[post("/<webhook_id>", data="<login>")]
+pub fn delete(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, webhook_id: u128) -> String {
+    let project_id = match project_of(db, webhook_id) {
+        Ok(p) => p,
+        Err(e) => return e
+    };
+    let result = login.authorize(db, Access::Admin, Target::Project(project_id));
+    match result {
+        LoginResult::Success(user_id) => {
+            Webhook::delete(&mut db.write().unwrap(), user_id, webhook_id);
+            utils::parse_response(Ok("success"))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+#[post("/<webhook_id>", data="<login>")]
+pub fn deliveries(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, webhook_id: u128) -> String {
+    let project_id = match project_of(db, webhook_id) {
+        Ok(p) => p,
+        Err(e) => return e
+    };
+    let result = login.authorize(db, Access::Admin, Target::Project(project_id));
+    match result {
+        LoginResult::Success(_) => {
+            let db = db.read().unwrap();
+            utils::parse_response(Ok(db.webhook_deliveries.get(&webhook_id).cloned().unwrap_or_default()))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+
+// sends a Ping straight away and answers with how it went, it is logged but never retried
+// the request can take up to webhook_timeout, so it runs on the blocking pool rather than an async worker
+#[post("/<webhook_id>", data="<login>")]
+pub async fn test(db: &State<Arc<RwLock<Database>>>, login: LoginInformation, webhook_id: u128) -> String {
+    let db = db.inner().clone();
+    task::spawn_blocking(move || ping(&db, login, webhook_id)).await.unwrap_or_default()
+}
+
+fn ping(db: &RwLock<Database>, login: LoginInformation, webhook_id: u128) -> String {
+    let project_id = match project_of(db, webhook_id) {
+        Ok(p) => p,
+        Err(e) => return e
+    };
+    let result = login.authorize(db, Access::Admin, Target::Project(project_id));
+    match result {
+        LoginResult::Success(_) => {
+            let event = Event {
+                project_id,
+                time: utils::get_time(),
+                change: Change::Ping
+            };
+            let mut job = match db.read().unwrap().webhooks.get(&webhook_id) {
+                Some(w) => w.job(0, webhook_id, &event),
+                None => return utils::parse_response(Err(WebhookError::WebhookNoExist))
+            };
+            job.attempt = 1;
+
+            // no lock is held while the request is out
+            let config = config::get();
+            let delivery = deliver(&agent(config.webhook_timeout, &config.webhook_allowed_hosts), &job);
+            Webhook::log(&mut db.write().unwrap(), webhook_id, delivery.clone());
+            utils::parse_response(Ok(delivery))
+        },
+        _ => utils::parse_response(Err(result))
+    }
+}
+// #endregion
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{Read, Write}, net::TcpListener, sync::mpsc, thread};
+
+    use super::*;
+
+    // answers each request with the next status, and passes the raw requests back
+    fn stand_in_server(statuses: Vec<u16>) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for (stream, status) in listener.incoming().zip(statuses) {
+                let mut stream = stream.unwrap();
+                let mut request = vec![];
+                let mut buffer = [0u8; 4096];
+                // headers, then as much body as content-length says
+                loop {
+                    let read = stream.read(&mut buffer).unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length = text.lines()
+                            .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                            .unwrap_or(0);
+                        if request.len() >= end + 4 + length {
+                            break;
+                        }
+                    }
+                }
+                // redirects point at the metadata address a webhook must never reach
+                let location = if (300..400).contains(&status) { "Location: http://169.254.169.254/\r\n" } else { "" };
+                stream.write_all(format!("HTTP/1.1 {status} X\r\n{location}Content-Length: 0\r\nConnection: close\r\n\r\n").as_bytes()).unwrap();
+                sender.send(String::from_utf8(request).unwrap()).unwrap();
+            }
+        });
+        (url, receiver)
+    }
+
+    fn job(url: String) -> Job {
+        Job {
+            id: 1,
+            webhook_id: 1,
+            url,
+            secret: "secret".to_string(),
+            event: "Ping".to_string(),
+            body: "{\"change\":{\"type\":\"Ping\"}}".to_string(),
+            attempt: 1,
+            due: 0
+        }
+    }
+
+    #[test]
+    fn delivers_signed_payloads() {
+        let (url, requests) = stand_in_server(vec![204, 500, 302]);
+        let agent = agent(5, &["127.0.0.1".to_string()]);
+
+        let delivery = deliver(&agent, &job(url.clone()));
+        assert_eq!(delivery.status, Some(204));
+        assert!(delivery.succeeded());
+
+        let request = requests.recv().unwrap();
+        let lower = request.to_ascii_lowercase();
+        assert!(request.starts_with("POST /hook"));
+        assert!(lower.contains(&format!("x-athena-signature: sha256={}", sign("secret", "{\"change\":{\"type\":\"Ping\"}}"))));
+        assert!(lower.contains("x-athena-event: ping"));
+        assert!(request.ends_with("{\"change\":{\"type\":\"Ping\"}}"));
+
+        let delivery = deliver(&agent, &job(url.clone()));
+        assert_eq!(delivery.status, Some(500));
+        assert!(!delivery.succeeded());
+
+        // redirects aren't followed
+        let delivery = deliver(&agent, &job(url));
+        assert_eq!(delivery.status, Some(302));
+        assert!(!delivery.succeeded());
+    }
+
+    #[test]
+    fn unreachable_receivers_fail_without_status() {
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}/hook", listener.local_addr().unwrap())
+        };
+        let delivery = deliver(&agent(5, &["127.0.0.1".to_string()]), &job(url));
+        assert_eq!(delivery.status, None);
+        assert!(delivery.error.is_some());
+    }
+
+    #[test]
+    fn queued_jobs_are_retried_until_they_run_out() {
+        config::init_temp();
+        let mut db = Database::default();
+        let webhook = |events: Vec<String>| Webhook { project_id: 1, url: "http://127.0.0.1:9/hook".to_string(), events, secret: "secret".to_string(), created_by: 1, created_at: 0 };
+        db.webhooks.insert(7, webhook(vec![]));
+        db.webhooks.insert(8, webhook(vec!["TaskDeleted".to_string()]));
+
+        // every event gets queued, however many come in at once
+        for i in 0..500 {
+            events::emit(&mut db, Some(1), Change::GroupDeleted { group_id: i });
+        }
+        events::emit(&mut db, Some(2), Change::ProjectDeleted);
+        assert_eq!(db.webhook_queue.len(), 500);
+        assert!(db.webhook_queue.iter().all(|j| j.webhook_id == 7));
+
+        let (due, _) = Webhook::due(&db, 0);
+        let failed = |attempt: u32| Delivery { event: "GroupDeleted".to_string(), attempt, time: 0, status: Some(500), error: None };
+        let ok = Delivery { status: Some(200), ..failed(1) };
+
+        let mut first = Job { attempt: 1, ..due[0].clone() };
+        Webhook::settle(&mut db, &first, failed(1), 2, 10);
+        let (due, wait) = Webhook::due(&db, utils::get_time());
+        assert_eq!(due.len(), 499);
+        assert!(wait <= 10);
+        assert_eq!(db.webhook_queue.iter().find(|j| j.id == first.id).map(|j| j.attempt), Some(1));
+
+        // out of attempts, it is dropped but the failures stay in the log
+        first.attempt = 2;
+        Webhook::settle(&mut db, &first, failed(2), 2, 10);
+        assert!(db.webhook_queue.iter().all(|j| j.id != first.id));
+        assert_eq!(db.webhook_deliveries[&7].len(), 2);
+
+        let second = Job { attempt: 1, ..due[0].clone() };
+        Webhook::settle(&mut db, &second, ok, 2, 10);
+        assert_eq!(db.webhook_queue.len(), 498);
+
+        // deleted webhooks aren't retried
+        let third = Job { attempt: 1, ..due[1].clone() };
+        db.webhooks.remove(&7);
+        Webhook::settle(&mut db, &third, failed(1), 2, 10);
+        assert_eq!(db.webhook_queue.len(), 497);
+    }
+
+    #[test]
+    fn only_public_destinations_unless_allowlisted() {
+        for private in ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "0.0.0.0", "100.64.0.1", "::1", "fe80::1", "fd00::1", "::ffff:127.0.0.1", "64:ff9b::a9fe:a9fe"] {
+            assert!(!is_public(private.parse().unwrap()), "{private}");
+        }
+        for public in ["1.1.1.1", "93.184.216.34", "2606:4700:4700::1111", "::ffff:1.1.1.1"] {
+            assert!(is_public(public.parse().unwrap()), "{public}");
+        }
+
+        assert_eq!(check_destination("http://169.254.169.254/latest/meta-data", &[]), Err(WebhookError::DestinationNotAllowed));
+        assert_eq!(check_destination("http://[::1]:8080/hook", &[]), Err(WebhookError::DestinationNotAllowed));
+        assert_eq!(check_destination("https://1.1.1.1/hook", &[]), Ok(()));
+        assert_eq!(check_destination("http://127.0.0.1:8080/hook", &["127.0.0.1".to_string()]), Ok(()));
+        assert_eq!(check_destination("not a url", &[]), Err(WebhookError::UrlInvalid));
+
+        // checked again on every delivery, against the addresses that are actually connected to
+        let (url, requests) = stand_in_server(vec![204]);
+        let blocked = deliver(&agent(5, &[]), &job(url));
+        assert_eq!(blocked.status, None);
+        assert!(blocked.error.is_some_and(|e| e.contains("DestinationNotAllowed")));
+        assert!(requests.try_recv().is_err());
+    }
+
+    #[test]
+    fn backoff_doubles() {
+        assert_eq!((1..=4).map(|a| backoff(10, a)).collect::<Vec<u64>>(), vec![10, 20, 40, 80]);
+        assert_eq!(backoff(u64::MAX, 40), u64::MAX);
+    }
+
+    #[test]
+    fn signature_matches_known_value() {
+        // RFC 4231 test case 2
+        assert_eq!(sign("Jefe", "what do ya want for nothing?"), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    }
+}